@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use log::debug;
+use log::trace;
+use log::warn;
+use nvml::struct_wrappers::device::AccountingStats;
+use nvml::Device;
+
+use crate::Metrics;
+
+/// Completed processes already accounted for, per device UUID.
+///
+/// Processes are keyed by `(pid, start_time)` so that a reused PID is counted again. Entries are
+/// dropped once their PID falls out of the driver's circular accounting buffer, but not when only
+/// querying their stats failed, so that a transient error does not count them twice.
+#[derive(Default)]
+pub struct SeenProcesses(Mutex<HashMap<String, HashSet<(u32, u64)>>>);
+
+/// Export summaries of processes that completed since the previous collection as counters.
+pub fn collect(metrics: &Metrics, seen: &SeenProcesses, device: &Device, dl: &[&str; 2]) {
+    match device.is_accounting_enabled() {
+        Ok(true) => (),
+        Ok(false) => {
+            debug!("accounting mode is disabled on device {}, skipping accounting stats", dl[0]);
+            return;
+        }
+        Err(e) => {
+            warn!("could not check accounting mode, skipping accounting stats: {:?}", e);
+            return;
+        }
+    }
+
+    let pids = match device.accounting_pids() {
+        Ok(pids) => pids,
+        Err(e) => {
+            warn!("error collecting accounting pids: {:?}", e);
+            return;
+        }
+    };
+
+    let mut seen = seen.0.lock().unwrap();
+    let seen = seen.entry(dl[1].to_string()).or_default();
+    let completed = newly_completed(seen, &pids, |pid| match device.accounting_stats_for(pid) {
+        Ok(stats) => Some(stats),
+        Err(e) => {
+            trace!("failed to collect accounting stats for pid {}: {:?}", pid, e);
+            None
+        }
+    });
+    for stats in completed {
+        let runtime = stats.time as f64 / 1000.;
+        metrics.cv_accounting_processes.with_label_values(dl).inc();
+        metrics.cv_accounting_runtime_seconds.with_label_values(dl).inc_by(runtime);
+        if let Some(util) = stats.gpu_utilization {
+            metrics.cv_accounting_gpu_seconds.with_label_values(dl).inc_by(runtime * util as f64 / 100.);
+        }
+        if let Some(max_memory) = stats.max_memory_usage {
+            metrics.cv_accounting_max_memory_bytes.with_label_values(dl).inc_by(max_memory as f64);
+        }
+    }
+}
+
+/// Stats of the completed processes among `pids` that were not `seen` before, marking them as
+/// seen and forgetting the processes whose PID is no longer listed. `stats_for` returns `None`
+/// when the stats of a PID could not be queried.
+fn newly_completed(seen: &mut HashSet<(u32, u64)>, pids: &[u32], stats_for: impl Fn(u32) -> Option<AccountingStats>) -> Vec<AccountingStats> {
+    let mut completed = vec![];
+    for &pid in pids {
+        let stats = match stats_for(pid) {
+            Some(stats) => stats,
+            None => continue,
+        };
+        if !stats.is_running && seen.insert((pid, stats.start_time)) {
+            completed.push(stats);
+        }
+    }
+    let listed: HashSet<u32> = pids.iter().copied().collect();
+    seen.retain(|(pid, _)| listed.contains(pid));
+    completed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(start_time: u64, is_running: bool) -> AccountingStats {
+        AccountingStats {
+            gpu_utilization: Some(50),
+            is_running,
+            max_memory_usage: Some(1 << 20),
+            memory_utilization: Some(10),
+            start_time,
+            time: 1000,
+        }
+    }
+
+    #[test]
+    fn counts_processes_once() {
+        let mut seen = HashSet::new();
+        let buffer = HashMap::from([(10, stats(100, false)), (11, stats(200, true))]);
+        let stats_for = |pid| buffer.get(&pid).cloned();
+
+        assert_eq!(newly_completed(&mut seen, &[10, 11], stats_for), [stats(100, false)]);
+        assert_eq!(newly_completed(&mut seen, &[10, 11], stats_for), []);
+    }
+
+    #[test]
+    fn counts_reused_pids_again() {
+        let mut seen = HashSet::new();
+        assert_eq!(newly_completed(&mut seen, &[10], |_| Some(stats(100, false))), [stats(100, false)]);
+        assert_eq!(newly_completed(&mut seen, &[10], |_| Some(stats(500, false))), [stats(500, false)]);
+    }
+
+    #[test]
+    fn forgets_processes_once_unlisted() {
+        let mut seen = HashSet::new();
+        newly_completed(&mut seen, &[10, 11], |pid| Some(stats(pid as u64, false)));
+
+        // querying the stats failed, but the PID is still in the buffer
+        assert_eq!(newly_completed(&mut seen, &[10, 11], |pid| (pid == 11).then(|| stats(11, false))), []);
+        assert_eq!(seen, HashSet::from([(10, 10), (11, 11)]));
+
+        // 10 dropped out of the circular buffer
+        assert_eq!(newly_completed(&mut seen, &[11], |pid| Some(stats(pid as u64, false))), []);
+        assert_eq!(seen, HashSet::from([(11, 11)]));
+    }
+}
@@ -0,0 +1,170 @@
+use log::trace;
+use nvml::enum_wrappers::device::Clock;
+use nvml::enum_wrappers::device::ClockId;
+use nvml::error::NvmlError;
+use nvml::Device;
+
+use crate::str_helpers::*;
+use crate::Metrics;
+
+pub const CLOCK_TYPES: [Clock; 4] = [Clock::Graphics, Clock::Memory, Clock::SM, Clock::Video];
+
+/// Clock queries of a device, so that clock collection can be tested without a GPU.
+pub trait ClockSource {
+    fn clock(&self, clock_type: Clock, clock_id: ClockId) -> Result<u32, NvmlError>;
+    fn applications_clock(&self, clock_type: Clock) -> Result<u32, NvmlError>;
+    fn default_applications_clock(&self, clock_type: Clock) -> Result<u32, NvmlError>;
+    fn max_clock_info(&self, clock_type: Clock) -> Result<u32, NvmlError>;
+    fn max_customer_boost_clock(&self, clock_type: Clock) -> Result<u32, NvmlError>;
+    fn supported_memory_clocks(&self) -> Result<Vec<u32>, NvmlError>;
+    fn supported_graphics_clocks(&self, for_mem_clock: u32) -> Result<Vec<u32>, NvmlError>;
+}
+
+impl ClockSource for Device<'_> {
+    fn clock(&self, clock_type: Clock, clock_id: ClockId) -> Result<u32, NvmlError> {
+        Device::clock(self, clock_type, clock_id)
+    }
+
+    fn applications_clock(&self, clock_type: Clock) -> Result<u32, NvmlError> {
+        Device::applications_clock(self, clock_type)
+    }
+
+    fn default_applications_clock(&self, clock_type: Clock) -> Result<u32, NvmlError> {
+        Device::default_applications_clock(self, clock_type)
+    }
+
+    fn max_clock_info(&self, clock_type: Clock) -> Result<u32, NvmlError> {
+        Device::max_clock_info(self, clock_type)
+    }
+
+    fn max_customer_boost_clock(&self, clock_type: Clock) -> Result<u32, NvmlError> {
+        Device::max_customer_boost_clock(self, clock_type)
+    }
+
+    fn supported_memory_clocks(&self) -> Result<Vec<u32>, NvmlError> {
+        Device::supported_memory_clocks(self)
+    }
+
+    fn supported_graphics_clocks(&self, for_mem_clock: u32) -> Result<Vec<u32>, NvmlError> {
+        Device::supported_graphics_clocks(self, for_mem_clock)
+    }
+}
+
+pub fn collect(metrics: &Metrics, device: &impl ClockSource, dl: &[&str; 2]) {
+    /*
+     * Only the "current" clock series seems to pull on @oko's RTX 3000 series card
+     */
+    for cid in &[ClockId::Current, ClockId::TargetAppClock, ClockId::DefaultAppClock, ClockId::CustomerMaxBoost] {
+        let cid_str = clock_id_str(cid.clone());
+        for ctype in &CLOCK_TYPES {
+            let ctype_str = clock_type_str(ctype.clone());
+            if let Ok(clock) = device.clock(ctype.clone(), cid.clone()) {
+                if cfg!(debug_assertions) {
+                    trace!("got metrics for clock ID {:?} and type {:?}", cid, ctype);
+                }
+                set_gv!(metrics.gv_clock, &[dl[0], dl[1], cid_str, ctype_str], clock as f64);
+            }
+        }
+    }
+
+    for ctype in &CLOCK_TYPES {
+        let ctype_str = clock_type_str(ctype.clone());
+        if let Ok(aclock) = device.applications_clock(ctype.clone()) {
+            set_gv!(metrics.gv_applications_clock, &[dl[0], dl[1], clock_id_str(ClockId::TargetAppClock), ctype_str], aclock as f64);
+        }
+        if let Ok(aclock) = device.default_applications_clock(ctype.clone()) {
+            set_gv!(metrics.gv_applications_clock, &[dl[0], dl[1], clock_id_str(ClockId::DefaultAppClock), ctype_str], aclock as f64);
+        }
+        if let Ok(max) = device.max_clock_info(ctype.clone()) {
+            set_gv!(metrics.gv_max_clock, &[dl[0], dl[1], ctype_str], max as f64);
+        }
+        if let Ok(boost) = device.max_customer_boost_clock(ctype.clone()) {
+            set_gv!(metrics.gv_max_customer_boost_clock, &[dl[0], dl[1], ctype_str], boost as f64);
+        }
+    }
+}
+
+/// Export the supported memory clocks and, for each of them, the supported graphics clocks.
+pub fn collect_supported(metrics: &Metrics, device: &impl ClockSource, dl: &[&str; 2]) -> Result<(), NvmlError> {
+    for mem_clock in device.supported_memory_clocks()? {
+        let mem_clock_string = mem_clock.to_string();
+        set_gv!(metrics.gv_supported_memory_clock, &[dl[0], dl[1], mem_clock_string.as_str()], 1);
+        for graphics_clock in device.supported_graphics_clocks(mem_clock)? {
+            let graphics_clock_string = graphics_clock.to_string();
+            set_gv!(metrics.gv_supported_graphics_clock, &[dl[0], dl[1], mem_clock_string.as_str(), graphics_clock_string.as_str()], 1);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Device whose applications clocks are set above its current clocks.
+    struct FakeDevice;
+
+    impl ClockSource for FakeDevice {
+        fn clock(&self, _clock_type: Clock, clock_id: ClockId) -> Result<u32, NvmlError> {
+            match clock_id {
+                ClockId::Current => Ok(1200),
+                _ => Err(NvmlError::NotSupported),
+            }
+        }
+
+        fn applications_clock(&self, _clock_type: Clock) -> Result<u32, NvmlError> {
+            Ok(1500)
+        }
+
+        fn default_applications_clock(&self, _clock_type: Clock) -> Result<u32, NvmlError> {
+            Ok(1400)
+        }
+
+        fn max_clock_info(&self, _clock_type: Clock) -> Result<u32, NvmlError> {
+            Ok(2000)
+        }
+
+        fn max_customer_boost_clock(&self, _clock_type: Clock) -> Result<u32, NvmlError> {
+            Err(NvmlError::NotSupported)
+        }
+
+        fn supported_memory_clocks(&self) -> Result<Vec<u32>, NvmlError> {
+            Ok(vec![5000, 810])
+        }
+
+        fn supported_graphics_clocks(&self, for_mem_clock: u32) -> Result<Vec<u32>, NvmlError> {
+            Ok(if for_mem_clock == 810 { vec![810] } else { vec![1500, 1200] })
+        }
+    }
+
+    #[test]
+    fn applications_clock_differs_from_current_clock() {
+        let metrics = crate::test_metrics();
+        collect(metrics, &FakeDevice, &["0", "GPU-clocks"]);
+
+        let clock = metrics.gv_clock.with_label_values(&["0", "GPU-clocks", "current", "graphics"]).get();
+        let target = metrics.gv_applications_clock.with_label_values(&["0", "GPU-clocks", "app_clock_target", "graphics"]).get();
+        let default = metrics.gv_applications_clock.with_label_values(&["0", "GPU-clocks", "app_clock_default", "graphics"]).get();
+        assert_ne!(clock, target);
+        assert_eq!(clock, 1200.);
+        assert_eq!(target, 1500.);
+        assert_eq!(default, 1400.);
+        assert_eq!(metrics.gv_max_clock.with_label_values(&["0", "GPU-clocks", "sm"]).get(), 2000.);
+    }
+
+    #[test]
+    fn pairs_graphics_clocks_with_memory_clocks() {
+        let metrics = crate::test_metrics();
+        collect_supported(metrics, &FakeDevice, &["0", "GPU-supported-clocks"]).unwrap();
+
+        for mem_clock in ["5000", "810"] {
+            assert_eq!(metrics.gv_supported_memory_clock.with_label_values(&["0", "GPU-supported-clocks", mem_clock]).get(), 1.);
+        }
+        for (mem_clock, graphics_clock) in [("5000", "1500"), ("5000", "1200"), ("810", "810")] {
+            assert_eq!(metrics.gv_supported_graphics_clock.with_label_values(&["0", "GPU-supported-clocks", mem_clock, graphics_clock]).get(), 1.);
+        }
+        let families = prometheus::core::Collector::collect(&metrics.gv_supported_graphics_clock);
+        let series = families[0].get_metric().iter().filter(|m| m.get_label().iter().any(|l| l.get_value() == "GPU-supported-clocks")).count();
+        assert_eq!(series, 3);
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+
+use flate2::write::GzEncoder;
+use flate2::write::ZlibEncoder;
+use hyper::body::Bytes;
+
+/// zstd level, the zstd default trading ratio for speed like gzip's default level.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Content coding of a compressed response.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Encoding {
+    Zstd,
+    Gzip,
+    Deflate,
+}
+
+/// Encodings in order of preference when the client accepts several equally.
+const ENCODINGS: [Encoding; 3] = [Encoding::Zstd, Encoding::Gzip, Encoding::Deflate];
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    /// Pick the encoding with the highest quality in an `Accept-Encoding` header, `None` to send
+    /// the response uncompressed.
+    pub fn negotiate(accept_encoding: Option<&str>) -> Option<Encoding> {
+        let mut qualities = HashMap::new();
+        for coding in accept_encoding.unwrap_or_default().split(',') {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default().to_ascii_lowercase();
+            let quality = parts.find_map(|param| param.strip_prefix("q=")).map_or(Some(1.), |q| q.trim().parse::<f64>().ok());
+            match quality {
+                Some(quality) if !name.is_empty() => {
+                    qualities.insert(if name == "x-gzip" { "gzip".to_string() } else { name }, quality);
+                }
+                _ => (),
+            }
+        }
+        let quality = |name: &str| qualities.get(name).or_else(|| qualities.get("*")).copied();
+
+        let (mut encoding, mut best) = (None, 0.);
+        for candidate in ENCODINGS {
+            let q = quality(candidate.as_str()).unwrap_or(0.);
+            if q > best {
+                (encoding, best) = (Some(candidate), q);
+            }
+        }
+        // identity is acceptable unless excluded, but only preferred when asked for explicitly
+        match quality("identity") {
+            Some(identity) if identity > best => None,
+            _ => encoding,
+        }
+    }
+
+    fn compress(&self, body: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Zstd => zstd::encode_all(body, ZSTD_LEVEL),
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Compresses response bodies of at least `threshold` bytes.
+pub struct Compression {
+    threshold: usize,
+}
+
+impl Compression {
+    pub fn new(threshold: usize) -> Self {
+        Compression { threshold }
+    }
+
+    /// Compress `body` with the encoding negotiated from `accept_encoding`, returning the encoding
+    /// used if any along with the body to send.
+    pub fn compress(&self, accept_encoding: Option<&str>, body: Bytes) -> io::Result<(Option<Encoding>, Bytes)> {
+        let encoding = match Encoding::negotiate(accept_encoding) {
+            Some(encoding) if body.len() >= self.threshold => encoding,
+            _ => return Ok((None, body)),
+        };
+        Ok((Some(encoding), Bytes::from(encoding.compress(&body)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+    use flate2::read::ZlibDecoder;
+
+    use super::*;
+
+    #[test]
+    fn negotiates_encodings() {
+        assert_eq!(Encoding::negotiate(None), None);
+        assert_eq!(Encoding::negotiate(Some("")), None);
+        assert_eq!(Encoding::negotiate(Some("gzip")), Some(Encoding::Gzip));
+        assert_eq!(Encoding::negotiate(Some("x-gzip")), Some(Encoding::Gzip));
+        assert_eq!(Encoding::negotiate(Some("gzip, deflate, br, zstd")), Some(Encoding::Zstd));
+        assert_eq!(Encoding::negotiate(Some("deflate;q=1, gzip;q=0.5")), Some(Encoding::Deflate));
+        assert_eq!(Encoding::negotiate(Some("*")), Some(Encoding::Zstd));
+        assert_eq!(Encoding::negotiate(Some("*, zstd;q=0")), Some(Encoding::Gzip));
+        assert_eq!(Encoding::negotiate(Some("gzip;q=0")), None);
+        assert_eq!(Encoding::negotiate(Some("identity, gzip;q=0.5")), None);
+        assert_eq!(Encoding::negotiate(Some("br")), None);
+    }
+
+    #[test]
+    fn compresses_above_threshold() {
+        let body = Bytes::from("nvml_temperature{device=\"0\"} 42\n".repeat(100));
+        let compression = Compression::new(1024);
+
+        let decompress = |encoding: &str| {
+            let (used, compressed) = compression.compress(Some(encoding), body.clone()).unwrap();
+            assert!(compressed.len() < body.len());
+            let mut decompressed = vec![];
+            match used.unwrap() {
+                Encoding::Zstd => decompressed = zstd::decode_all(&compressed[..]).unwrap(),
+                Encoding::Gzip => drop(GzDecoder::new(&compressed[..]).read_to_end(&mut decompressed).unwrap()),
+                Encoding::Deflate => drop(ZlibDecoder::new(&compressed[..]).read_to_end(&mut decompressed).unwrap()),
+            }
+            decompressed
+        };
+        for encoding in ["zstd", "gzip", "deflate"] {
+            assert_eq!(decompress(encoding), body, "{}", encoding);
+        }
+
+        let small = Bytes::from_static(b"nvml_device_count 8\n");
+        assert_eq!(compression.compress(Some("gzip"), small.clone()).unwrap(), (None, small));
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use prometheus::core::Collector;
+use prometheus::CounterVec;
+
+/// Last raw values of cumulative driver counters, to export them through prometheus counters.
+///
+/// The first observation of a series sets the counter to the raw value, later observations
+/// increment it by the difference. A raw value going backwards (e.g. on driver reload) is
+/// treated as a counter reset. Series are keyed by counter name and labels, so a single instance
+/// can track any number of counters.
+#[derive(Default)]
+pub struct Cumulative(Mutex<HashMap<Vec<String>, f64>>);
+
+impl Cumulative {
+    pub fn observe(&self, counter: &CounterVec, labels: &[&str], value: f64) {
+        let mut last = self.0.lock().unwrap();
+        let mut key = counter.desc().iter().map(|d| d.fq_name.clone()).collect::<Vec<_>>();
+        key.extend(labels.iter().map(|l| l.to_string()));
+        let delta = match last.get(&key) {
+            Some(previous) if value >= *previous => value - previous,
+            _ => value,
+        };
+        counter.with_label_values(labels).inc_by(delta);
+        last.insert(key, value);
+    }
+
+    /// Names of the counters observed so far.
+    pub fn counters(&self) -> HashSet<String> {
+        self.0.lock().unwrap().keys().map(|key| key[0].clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::Opts;
+
+    use super::*;
+
+    fn violation_counter() -> CounterVec {
+        CounterVec::new(Opts::new("test_violation_seconds_total", "violation time"), &["device", "reason"]).unwrap()
+    }
+
+    #[test]
+    fn first_observation_sets_raw_value() {
+        let cumulative = Cumulative::default();
+        let counter = violation_counter();
+        cumulative.observe(&counter, &["0", "power"], 12.5);
+        assert_eq!(counter.with_label_values(&["0", "power"]).get(), 12.5);
+        assert_eq!(cumulative.counters(), HashSet::from(["test_violation_seconds_total".to_string()]));
+    }
+
+    #[test]
+    fn increments_by_difference() {
+        let cumulative = Cumulative::default();
+        let counter = violation_counter();
+        cumulative.observe(&counter, &["0", "power"], 10.);
+        cumulative.observe(&counter, &["0", "power"], 14.);
+        cumulative.observe(&counter, &["0", "power"], 14.);
+        cumulative.observe(&counter, &["0", "thermal"], 3.);
+        assert_eq!(counter.with_label_values(&["0", "power"]).get(), 14.);
+        assert_eq!(counter.with_label_values(&["0", "thermal"]).get(), 3.);
+    }
+
+    #[test]
+    fn raw_value_going_backwards_is_a_reset() {
+        let cumulative = Cumulative::default();
+        let counter = violation_counter();
+        cumulative.observe(&counter, &["0", "power"], 10.);
+        cumulative.observe(&counter, &["0", "power"], 2.);
+        assert_eq!(counter.with_label_values(&["0", "power"]).get(), 12.);
+        cumulative.observe(&counter, &["0", "power"], 5.);
+        assert_eq!(counter.with_label_values(&["0", "power"]).get(), 15.);
+    }
+}
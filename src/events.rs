@@ -0,0 +1,143 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::SystemTime;
+
+use log::debug;
+use log::error;
+use log::info;
+use log::warn;
+use nvml::bitmasks::event::EventTypes;
+use nvml::enums::event::XidError;
+use nvml::error::NvmlError;
+use nvml::struct_wrappers::event::EventData;
+use nvml::EventSet;
+
+use crate::Context;
+
+/// How long a single wait for events blocks, which bounds how long shutdown takes.
+const WAIT_TIMEOUT_MS: u32 = 500;
+
+/// Start a thread listening for XID, ECC and clock change events on all devices until `ctx.shutdown` is set.
+pub fn spawn(ctx: Arc<Context>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let set = match register(&ctx) {
+            Ok(Some(set)) => set,
+            Ok(None) => {
+                warn!("no device supports XID or ECC events, not listening for events");
+                return;
+            }
+            Err(e) => {
+                error!("error registering for NVML events: {:?}", e);
+                return;
+            }
+        };
+        info!("listening for NVML events");
+
+        while !ctx.shutdown.load(Ordering::Relaxed) {
+            match set.wait(WAIT_TIMEOUT_MS) {
+                Ok(event) => {
+                    if let Err(e) = record(&ctx, event) {
+                        warn!("error recording NVML event: {:?}", e);
+                    }
+                }
+                Err(NvmlError::Timeout) => (),
+                Err(e) => {
+                    error!("error waiting for NVML events: {:?}", e);
+                    thread::sleep(std::time::Duration::from_millis(WAIT_TIMEOUT_MS as u64));
+                }
+            }
+        }
+        debug!("stopped listening for NVML events");
+    })
+}
+
+/// Register the supported events of every device in a single event set, skipping devices that fail.
+fn register(ctx: &Context) -> Result<Option<EventSet<'_>>, NvmlError> {
+    let wanted = EventTypes::CRITICAL_XID_ERROR | EventTypes::SINGLE_BIT_ECC_ERROR | EventTypes::DOUBLE_BIT_ECC_ERROR | EventTypes::CLOCK_CHANGE;
+    let set = ctx.nvml.create_event_set()?;
+    let mut registered = false;
+    for device_index in 0..ctx.nvml.device_count()? {
+        let device = match ctx.nvml.device_by_index(device_index) {
+            Ok(device) => device,
+            Err(e) => {
+                warn!("could not get device {}, not listening for its events: {:?}", device_index, e);
+                continue;
+            }
+        };
+        let events = match device.supported_event_types() {
+            Ok(supported) => supported & wanted,
+            Err(e) => {
+                warn!("could not check supported events of device {}: {:?}", device_index, e);
+                continue;
+            }
+        };
+        if events.is_empty() {
+            continue;
+        }
+        match ctx.raw.register_events(&device, events, &set) {
+            Ok(()) => (),
+            // the set is left in an undefined state
+            Err(NvmlError::Unknown) => return Err(NvmlError::Unknown),
+            Err(e) => {
+                warn!("could not register events {:?} on device {}: {:?}", events, device_index, e);
+                continue;
+            }
+        }
+        debug!("registered events {:?} on device {}", events, device_index);
+        registered = true;
+        if let Ok(uuid) = device.uuid() {
+            initialize(ctx, &[&device_index.to_string(), &uuid], events);
+        }
+    }
+    Ok(registered.then_some(set))
+}
+
+/// Export the event counters of a device at zero, so that `rate()` and `increase()` see the first event.
+fn initialize(ctx: &Context, dl: &[&str; 2], events: EventTypes) {
+    if events.contains(EventTypes::CRITICAL_XID_ERROR) {
+        ctx.metrics.cv_xid_events.with_label_values(dl);
+    }
+    if events.contains(EventTypes::SINGLE_BIT_ECC_ERROR) {
+        ctx.metrics.cv_ecc_error_events.with_label_values(&[dl[0], dl[1], "single_bit"]);
+    }
+    if events.contains(EventTypes::DOUBLE_BIT_ECC_ERROR) {
+        ctx.metrics.cv_ecc_error_events.with_label_values(&[dl[0], dl[1], "double_bit"]);
+    }
+    if events.contains(EventTypes::CLOCK_CHANGE) {
+        ctx.metrics.cv_clock_change_events.with_label_values(dl);
+    }
+}
+
+fn record(ctx: &Context, event: EventData) -> Result<(), NvmlError> {
+    let dev_idx_string = event.device.index()?.to_string();
+    let dev_uuid_string = event.device.uuid()?;
+    let dl = &[dev_idx_string.as_str(), dev_uuid_string.as_str()];
+
+    if event.event_type.contains(EventTypes::CRITICAL_XID_ERROR) {
+        let xid = match event.event_data {
+            Some(XidError::Value(xid)) => Some(xid),
+            _ => None,
+        };
+        let xid_string = xid.map(|x| x.to_string()).unwrap_or_else(|| "unknown".to_string());
+        warn!("XID {} on device {} ({})", xid_string, dl[0], dl[1]);
+        ctx.metrics.cv_xid_errors.with_label_values(&[dl[0], dl[1], xid_string.as_str()]).inc();
+        ctx.metrics.cv_xid_events.with_label_values(dl).inc();
+        if let Some(xid) = xid {
+            set_gv!(ctx.metrics.gv_last_xid, dl, xid);
+        }
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+        set_gv!(ctx.metrics.gv_last_xid_timestamp, dl, now.as_secs_f64());
+    }
+    if event.event_type.contains(EventTypes::SINGLE_BIT_ECC_ERROR) {
+        ctx.metrics.cv_ecc_error_events.with_label_values(&[dl[0], dl[1], "single_bit"]).inc();
+    }
+    if event.event_type.contains(EventTypes::DOUBLE_BIT_ECC_ERROR) {
+        ctx.metrics.cv_ecc_error_events.with_label_values(&[dl[0], dl[1], "double_bit"]).inc();
+    }
+    if event.event_type.contains(EventTypes::CLOCK_CHANGE) {
+        ctx.metrics.cv_clock_change_events.with_label_values(dl).inc();
+    }
+    Ok(())
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use prometheus::proto::LabelPair;
+use prometheus::proto::Metric;
+use prometheus::proto::MetricFamily;
+use prometheus::proto::MetricType;
+use prometheus::Encoder;
+use prometheus::ProtobufEncoder;
+use prometheus::TextEncoder;
+
+const OPENMETRICS_FORMAT: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+const PROTOBUF_PROTO: &str = "io.prometheus.client.MetricFamily";
+
+/// Units exported as `# UNIT` when metric names end with them, longest first.
+const UNITS: [&str; 9] = ["bytes_per_second", "seconds", "bytes", "joules", "watts", "volts", "amperes", "celsius", "hertz"];
+
+/// Exposition format, negotiated from the `Accept` header of a scrape.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    OpenMetrics,
+    Protobuf,
+    /// The classic Prometheus text format, served unless another format is preferred.
+    Text,
+}
+
+impl Format {
+    /// Pick the supported format with the highest quality, the earliest one on ties.
+    pub fn negotiate(accept: Option<&str>) -> Format {
+        let mut best = (Format::Text, 0.);
+        for range in accept.unwrap_or_default().split(',') {
+            let mut parts = range.split(';').map(str::trim);
+            let media_type = parts.next().unwrap_or_default().to_ascii_lowercase();
+            let params = parts
+                .filter_map(|param| param.split_once('='))
+                .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().trim_matches('"')))
+                .collect::<HashMap<_, _>>();
+            let format = match media_type.as_str() {
+                // only 1.0.0 is served, 0.0.1 clients fall back to another format
+                "application/openmetrics-text" if matches!(params.get("version"), None | Some(&"1.0.0")) => Format::OpenMetrics,
+                "application/vnd.google.protobuf" if params.get("proto") == Some(&PROTOBUF_PROTO) && params.get("encoding") == Some(&"delimited") => Format::Protobuf,
+                "text/plain" if matches!(params.get("version"), None | Some(&"0.0.4")) => Format::Text,
+                _ => continue,
+            };
+            let quality = params.get("q").and_then(|q| q.parse::<f64>().ok()).unwrap_or(1.);
+            if quality > best.1 {
+                best = (format, quality);
+            }
+        }
+        best.0
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::OpenMetrics => OPENMETRICS_FORMAT,
+            Format::Protobuf => prometheus::PROTOBUF_FORMAT,
+            Format::Text => prometheus::TEXT_FORMAT,
+        }
+    }
+
+    /// Encode `families`, recording when counters were created whatever the format so that
+    /// switching formats does not reset `_created`. The `seeded` counter families start from
+    /// totals accrued before the exporter started, so they have no `_created`.
+    pub fn encode(&self, families: &[MetricFamily], created: &Created, seeded: &HashSet<String>) -> prometheus::Result<Vec<u8>> {
+        let created = created.update(families, seeded);
+        let mut buffer = vec![];
+        match self {
+            Format::OpenMetrics => buffer = encode_openmetrics(families, &created).into_bytes(),
+            Format::Protobuf => ProtobufEncoder::new().encode(families, &mut buffer)?,
+            Format::Text => TextEncoder::new().encode(families, &mut buffer)?,
+        }
+        Ok(buffer)
+    }
+}
+
+type SeriesKey = (String, Vec<(String, String)>);
+
+fn series_key(family: &MetricFamily, metric: &Metric) -> SeriesKey {
+    (family.get_name().to_string(), metric.get_label().iter().map(|l| (l.get_name().to_string(), l.get_value().to_string())).collect())
+}
+
+/// When each counter, histogram and summary series was first exported, as its `_created`
+/// timestamp in seconds. Series that disappear are forgotten, so that they are created anew.
+#[derive(Default)]
+pub struct Created(Mutex<HashMap<SeriesKey, f64>>);
+
+impl Created {
+    fn update(&self, families: &[MetricFamily], seeded: &HashSet<String>) -> HashMap<SeriesKey, f64> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+        let mut created = self.0.lock().unwrap();
+        let mut current = HashMap::new();
+        for family in families {
+            if !matches!(family.get_field_type(), MetricType::COUNTER | MetricType::HISTOGRAM | MetricType::SUMMARY) || seeded.contains(family.get_name()) {
+                continue;
+            }
+            for metric in family.get_metric() {
+                let key = series_key(family, metric);
+                let timestamp = *created.get(&key).unwrap_or(&now);
+                current.insert(key, timestamp);
+            }
+        }
+        created.clone_from(&current);
+        current
+    }
+}
+
+/// Encode families in the OpenMetrics text format, naming counter families without their
+/// `_total` suffix as OpenMetrics requires.
+fn encode_openmetrics(families: &[MetricFamily], created: &HashMap<SeriesKey, f64>) -> String {
+    let mut out = String::new();
+    for family in families {
+        let (kind, name) = match family.get_field_type() {
+            MetricType::COUNTER => ("counter", family.get_name().strip_suffix("_total").unwrap_or(family.get_name())),
+            MetricType::GAUGE => ("gauge", family.get_name()),
+            MetricType::HISTOGRAM => ("histogram", family.get_name()),
+            MetricType::SUMMARY => ("summary", family.get_name()),
+            MetricType::UNTYPED => ("unknown", family.get_name()),
+        };
+        writeln!(out, "# HELP {} {}", name, escape(family.get_help(), true)).unwrap();
+        writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+        if let Some(unit) = UNITS.iter().find(|unit| name.ends_with(&format!("_{unit}"))) {
+            writeln!(out, "# UNIT {} {}", name, unit).unwrap();
+        }
+
+        for metric in family.get_metric() {
+            let labels = metric.get_label();
+            let created = created.get(&series_key(family, metric));
+            let timestamp = match metric.get_timestamp_ms() {
+                0 => String::new(),
+                ms => format!(" {}", ms as f64 / 1000.),
+            };
+            let mut sample = |suffix: &str, extra: Option<(&str, String)>, value: f64| {
+                writeln!(out, "{}{}{} {}{}", name, suffix, format_labels(labels, extra), format_value(value), timestamp).unwrap();
+            };
+            match family.get_field_type() {
+                MetricType::COUNTER => sample("_total", None, metric.get_counter().get_value()),
+                MetricType::GAUGE => sample("", None, metric.get_gauge().get_value()),
+                MetricType::UNTYPED => sample("", None, metric.get_untyped().get_value()),
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    for bucket in histogram.get_bucket() {
+                        sample("_bucket", Some(("le", format_value(bucket.get_upper_bound()))), bucket.get_cumulative_count() as f64);
+                    }
+                    if histogram.get_bucket().last().map(|bucket| bucket.get_upper_bound()) != Some(f64::INFINITY) {
+                        sample("_bucket", Some(("le", "+Inf".to_string())), histogram.get_sample_count() as f64);
+                    }
+                    sample("_count", None, histogram.get_sample_count() as f64);
+                    sample("_sum", None, histogram.get_sample_sum());
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    for quantile in summary.get_quantile() {
+                        sample("", Some(("quantile", format_value(quantile.get_quantile()))), quantile.get_value());
+                    }
+                    sample("_count", None, summary.get_sample_count() as f64);
+                    sample("_sum", None, summary.get_sample_sum());
+                }
+            }
+            if let Some(created) = created {
+                writeln!(out, "{}_created{} {}", name, format_labels(labels, None), format_value(*created)).unwrap();
+            }
+        }
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+fn format_labels(labels: &[LabelPair], extra: Option<(&str, String)>) -> String {
+    let pairs = labels
+        .iter()
+        .map(|l| (l.get_name(), l.get_value().to_string()))
+        .chain(extra)
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(&value, true)))
+        .collect::<Vec<_>>();
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn escape(s: &str, quotes: bool) -> String {
+    let s = s.replace('\\', "\\\\").replace('\n', "\\n");
+    if quotes {
+        s.replace('"', "\\\"")
+    } else {
+        s
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use prometheus::core::Collector;
+    use prometheus::register_counter_vec_with_registry;
+    use prometheus::register_gauge_with_registry;
+    use prometheus::Registry;
+
+    use super::*;
+    use crate::cumulative::Cumulative;
+
+    const PROMETHEUS_ACCEPT: &str = "application/openmetrics-text;version=1.0.0,application/openmetrics-text;version=0.0.1;q=0.75,text/plain;version=0.0.4;q=0.5,*/*;q=0.1";
+
+    pub fn families() -> Vec<MetricFamily> {
+        let registry = Registry::new();
+        let counter = register_counter_vec_with_registry!("nvml_throttle_violation_seconds_total", "time during which clocks were \"limited\"", &["device", "reason"], registry).unwrap();
+        counter.with_label_values(&["0", "power \"cap\""]).inc_by(1.5);
+        let gauge = register_gauge_with_registry!("nvml_device_count", "number of nvml devices", registry).unwrap();
+        gauge.set(2.);
+        registry.gather()
+    }
+
+    #[test]
+    fn negotiates_formats() {
+        assert_eq!(Format::negotiate(None), Format::Text);
+        assert_eq!(Format::negotiate(Some("*/*")), Format::Text);
+        assert_eq!(Format::negotiate(Some("text/plain;version=0.0.4")), Format::Text);
+        assert_eq!(Format::negotiate(Some(PROMETHEUS_ACCEPT)), Format::OpenMetrics);
+        assert_eq!(Format::negotiate(Some("application/openmetrics-text;version=0.0.1,text/plain;q=0.5")), Format::Text);
+        assert_eq!(Format::negotiate(Some("application/openmetrics-text;version=2.0.0,text/plain;q=0.5")), Format::Text);
+        assert_eq!(
+            Format::negotiate(Some(
+                "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;encoding=delimited;q=0.7,text/plain;version=0.0.4;q=0.3,*/*;q=0.2"
+            )),
+            Format::Protobuf
+        );
+        assert_eq!(Format::negotiate(Some("application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;encoding=text")), Format::Text);
+        assert_eq!(Format::negotiate(Some("text/plain;q=0.5, application/openmetrics-text;q=0.5")), Format::Text);
+    }
+
+    #[test]
+    fn encodes_openmetrics() {
+        let created = Created::default();
+        let body = String::from_utf8(Format::OpenMetrics.encode(&families(), &created, &Default::default()).unwrap()).unwrap();
+        let lines = body.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[..4],
+            [
+                "# HELP nvml_device_count number of nvml devices",
+                "# TYPE nvml_device_count gauge",
+                "nvml_device_count 2",
+                "# HELP nvml_throttle_violation_seconds time during which clocks were \\\"limited\\\""
+            ]
+        );
+        assert_eq!(
+            lines[4..7],
+            [
+                "# TYPE nvml_throttle_violation_seconds counter",
+                "# UNIT nvml_throttle_violation_seconds seconds",
+                "nvml_throttle_violation_seconds_total{device=\"0\",reason=\"power \\\"cap\\\"\"} 1.5"
+            ]
+        );
+        assert!(lines[7].starts_with("nvml_throttle_violation_seconds_created{device=\"0\",reason=\"power \\\"cap\\\"\"} "));
+        assert_eq!(lines[8..], ["# EOF"]);
+
+        // the creation time sticks across scrapes
+        let again = String::from_utf8(Format::OpenMetrics.encode(&families(), &created, &Default::default()).unwrap()).unwrap();
+        assert_eq!(again, body);
+
+        // cumulative driver counts are counters, so they get `_total`, but no `_created` since
+        // they include what was counted before the exporter started
+        let metrics = crate::test_metrics();
+        let cumulative = Cumulative::default();
+        cumulative.observe(&metrics.cv_energy, &["0", "GPU-openmetrics"], 1234.5);
+        cumulative.observe(&metrics.cv_memory_errors, &["0", "GPU-openmetrics", "corrected", "aggregate", "device_memory"], 3.);
+        let families = [metrics.cv_energy.collect(), metrics.cv_memory_errors.collect(), families()].concat();
+        let body = String::from_utf8(Format::OpenMetrics.encode(&families, &Created::default(), &cumulative.counters()).unwrap()).unwrap();
+        let lines = body.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[1..4],
+            [
+                "# TYPE nvml_energy_joules counter",
+                "# UNIT nvml_energy_joules joules",
+                "nvml_energy_joules_total{device=\"0\",uuid=\"GPU-openmetrics\"} 1234.5"
+            ]
+        );
+        let labels = "{device=\"0\",ecc_counter=\"aggregate\",mem_error=\"corrected\",mem_location=\"device_memory\",uuid=\"GPU-openmetrics\"}";
+        assert_eq!(lines[5..7], ["# TYPE nvml_memory_errors counter", &format!("nvml_memory_errors_total{labels} 3")]);
+        assert!(lines[7].starts_with("# HELP nvml_device_count "));
+        // only counters the exporter started from zero have `_created`
+        assert_eq!(lines.iter().filter(|line| line.contains("_created")).count(), 1);
+        assert!(lines[14].starts_with("nvml_throttle_violation_seconds_created{"));
+    }
+
+    #[test]
+    fn encodes_protobuf_and_text() {
+        let protobuf = Format::Protobuf.encode(&families(), &Created::default(), &Default::default()).unwrap();
+        // delimited: each family is prefixed with its varint encoded length
+        let (mut offset, mut frames) = (0, 0);
+        while offset < protobuf.len() {
+            let (mut len, mut shift) = (0, 0);
+            loop {
+                let byte = protobuf[offset];
+                offset += 1;
+                len |= ((byte & 0x7f) as usize) << shift;
+                shift += 7;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            offset += len;
+            frames += 1;
+        }
+        assert_eq!((offset, frames), (protobuf.len(), 2));
+        let text = String::from_utf8(Format::Text.encode(&families(), &Created::default(), &Default::default()).unwrap()).unwrap();
+        assert!(text.contains("# TYPE nvml_throttle_violation_seconds_total counter\n"));
+        assert!(!text.contains("# EOF"));
+    }
+}
@@ -0,0 +1,506 @@
+//! Direct NVML calls for queries `nvml-wrapper` does not cover yet.
+
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::os::raw::c_uint;
+#[cfg(target_os = "linux")]
+use std::os::raw::c_ulong;
+use std::os::raw::c_ulonglong;
+
+#[cfg(target_os = "linux")]
+use nvml::bitmasks::event::EventTypes;
+#[cfg(target_os = "linux")]
+use nvml::enum_wrappers::device::P2pStatus;
+use nvml::enum_wrappers::device::SampleValueType;
+use nvml::enums::device::SampleValue;
+use nvml::error::nvml_sym;
+use nvml::error::nvml_try;
+use nvml::error::NvmlError;
+use nvml::struct_wrappers::device::FbcStats;
+use nvml::structs::device::EncoderStats;
+use nvml::Device;
+#[cfg(target_os = "linux")]
+use nvml::EventSet;
+use nvml_wrapper_sys::bindings::nvmlDeviceAttributes_t;
+use nvml_wrapper_sys::bindings::nvmlDevice_t;
+use nvml_wrapper_sys::bindings::nvmlEncoderSessionInfo_t;
+use nvml_wrapper_sys::bindings::nvmlFBCStats_t;
+use nvml_wrapper_sys::bindings::nvmlFieldValue_t;
+use nvml_wrapper_sys::bindings::nvmlGpmMetricId_t;
+use nvml_wrapper_sys::bindings::nvmlGpmMetricsGet_t;
+use nvml_wrapper_sys::bindings::nvmlGpmSample_t;
+use nvml_wrapper_sys::bindings::nvmlGpmSupport_t;
+#[cfg(target_os = "linux")]
+use nvml_wrapper_sys::bindings::nvmlGpuP2PCapsIndex_t;
+#[cfg(target_os = "linux")]
+use nvml_wrapper_sys::bindings::nvmlGpuP2PStatus_t;
+use nvml_wrapper_sys::bindings::nvmlGpuVirtualizationMode_NVML_GPU_VIRTUALIZATION_MODE_HOST_VGPU;
+use nvml_wrapper_sys::bindings::nvmlMemory_v2_t;
+use nvml_wrapper_sys::bindings::nvmlReturn_enum_NVML_ERROR_INSUFFICIENT_SIZE;
+use nvml_wrapper_sys::bindings::nvmlReturn_enum_NVML_SUCCESS;
+use nvml_wrapper_sys::bindings::nvmlReturn_t;
+use nvml_wrapper_sys::bindings::nvmlRowRemapperHistogramValues_t;
+use nvml_wrapper_sys::bindings::nvmlVgpuInstance_t;
+use nvml_wrapper_sys::bindings::nvmlVgpuTypeId_t;
+use nvml_wrapper_sys::bindings::nvmlVgpuVmIdType_t;
+use nvml_wrapper_sys::bindings::NvmlLib;
+#[cfg(target_os = "linux")]
+use nvml_wrapper_sys::bindings::NVML_AFFINITY_SCOPE_NODE;
+use nvml_wrapper_sys::bindings::NVML_DEVICE_MIG_ENABLE;
+use nvml_wrapper_sys::bindings::NVML_DEVICE_UUID_BUFFER_SIZE;
+use nvml_wrapper_sys::bindings::NVML_GPM_METRICS_GET_VERSION;
+use nvml_wrapper_sys::bindings::NVML_GPM_SUPPORT_VERSION;
+use nvml_wrapper_sys::bindings::NVML_VGPU_NAME_BUFFER_SIZE;
+
+#[cfg(target_os = "windows")]
+const LIB_PATH: &str = "nvml.dll";
+
+#[cfg(not(target_os = "windows"))]
+const LIB_PATH: &str = "libnvidia-ml.so";
+
+/// `NVML_ENCODER_QUERY_AV1`, added in driver 520.
+pub const ENCODER_QUERY_AV1: c_uint = 2;
+
+/// `nvmlMemory_v2` as defined by `NVML_STRUCT_VERSION(Memory, 2)`.
+const MEMORY_V2: c_uint = std::mem::size_of::<nvmlMemory_v2_t>() as c_uint | (2 << 24);
+
+/// Size of the buffer for VM IDs, `NVML_DEVICE_UUID_BUFFER_SIZE` is the documented maximum.
+const VM_ID_BUFFER_SIZE: u32 = NVML_DEVICE_UUID_BUFFER_SIZE;
+
+/// Signature shared by the queries returning lists of vGPU instances or types of a device.
+type VgpuListFn = unsafe extern "C" fn(nvmlDevice_t, *mut c_uint, *mut c_uint) -> nvmlReturn_t;
+
+fn string_from_buf(buf: &[c_char]) -> String {
+    unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy().into_owned()
+}
+
+/// Call a list query twice, once for the size and once for the values.
+#[allow(non_upper_case_globals)]
+unsafe fn vgpu_list(sym: &VgpuListFn, device: &Device) -> Result<Vec<c_uint>, NvmlError> {
+    let mut count: c_uint = 0;
+    match sym(device.handle(), &mut count, std::ptr::null_mut()) {
+        nvmlReturn_enum_NVML_SUCCESS => return Ok(vec![]),
+        nvmlReturn_enum_NVML_ERROR_INSUFFICIENT_SIZE => (),
+        other => nvml_try(other)?,
+    }
+    let mut values: Vec<c_uint> = vec![0; count as usize];
+    nvml_try(sym(device.handle(), &mut count, values.as_mut_ptr()))?;
+    values.truncate(count as usize);
+    Ok(values)
+}
+
+/// Current and pending MIG mode of a device.
+pub struct MigMode {
+    pub current: bool,
+    pub pending: bool,
+}
+
+/// Row remapping state of a device.
+pub struct RemappedRows {
+    pub correctable: u32,
+    pub uncorrectable: u32,
+    pub pending: bool,
+    pub failure: bool,
+}
+
+/// GPM sample of a device, freed when dropped.
+///
+/// Samples must be dropped before NVML is shut down.
+pub struct GpmSample {
+    sample: nvmlGpmSample_t,
+    free: unsafe extern "C" fn(nvmlGpmSample_t) -> nvmlReturn_t,
+}
+
+// The sample is an opaque buffer owned by this struct, NVML itself is thread-safe.
+unsafe impl Send for GpmSample {}
+
+impl Drop for GpmSample {
+    fn drop(&mut self) {
+        unsafe {
+            (self.free)(self.sample);
+        }
+    }
+}
+
+/// Second handle on the NVML library, sharing the state of the library initialized by [`nvml::Nvml`].
+/// `nvmlGpmMigSampleGet`, which the bindings of `nvml-wrapper-sys` do not include yet.
+type GpmMigSampleGet = unsafe extern "C" fn(nvmlDevice_t, c_uint, nvmlGpmSample_t) -> nvmlReturn_t;
+
+pub struct RawNvml {
+    lib: NvmlLib,
+    gpm_mig_sample_get: Result<GpmMigSampleGet, libloading::Error>,
+    // keeps `gpm_mig_sample_get` loaded
+    _extra_lib: libloading::Library,
+}
+
+impl RawNvml {
+    /// Load the NVML library. Must be called after `Nvml::init()`, it does not initialize NVML on its own.
+    pub fn load() -> Result<Self, NvmlError> {
+        let lib = unsafe { NvmlLib::new(LIB_PATH)? };
+        let extra_lib = unsafe { libloading::Library::new(LIB_PATH)? };
+        let gpm_mig_sample_get = unsafe { extra_lib.get::<GpmMigSampleGet>(b"nvmlGpmMigSampleGet\0").map(|sym| *sym) };
+        Ok(RawNvml {
+            lib,
+            gpm_mig_sample_get,
+            _extra_lib: extra_lib,
+        })
+    }
+
+    pub fn encoder_capacity_av1(&self, device: &Device) -> Result<u32, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceGetEncoderCapacity.as_ref())?;
+        unsafe {
+            let mut capacity: c_uint = 0;
+            nvml_try(sym(device.handle(), ENCODER_QUERY_AV1, &mut capacity))?;
+            Ok(capacity)
+        }
+    }
+
+    /// Encoder sessions of a device, with the raw codec type so that codecs newer than
+    /// `nvml-wrapper`'s `EncoderType`, such as AV1, do not fail the whole list.
+    pub fn encoder_sessions(&self, device: &Device) -> Result<Vec<nvmlEncoderSessionInfo_t>, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceGetEncoderSessions.as_ref())?;
+        unsafe {
+            let mut count: c_uint = 0;
+            nvml_try(sym(device.handle(), &mut count, std::ptr::null_mut()))?;
+            if count == 0 {
+                return Ok(vec![]);
+            }
+            let mut sessions: Vec<nvmlEncoderSessionInfo_t> = vec![std::mem::zeroed(); count as usize];
+            nvml_try(sym(device.handle(), &mut count, sessions.as_mut_ptr()))?;
+            sessions.truncate(count as usize);
+            Ok(sessions)
+        }
+    }
+
+    /// Register `events` of a device in `set`. Unlike `Device::register_events()`, the set is not
+    /// released on failure, so that the events of other devices keep being delivered.
+    #[cfg(target_os = "linux")]
+    pub fn register_events(&self, device: &Device, events: EventTypes, set: &EventSet) -> Result<(), NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceRegisterEvents.as_ref())?;
+        unsafe { nvml_try(sym(device.handle(), events.bits(), set.handle())) }
+    }
+
+    pub fn mig_mode(&self, device: &Device) -> Result<MigMode, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceGetMigMode.as_ref())?;
+        unsafe {
+            let mut current: c_uint = 0;
+            let mut pending: c_uint = 0;
+            nvml_try(sym(device.handle(), &mut current, &mut pending))?;
+            Ok(MigMode {
+                current: current == NVML_DEVICE_MIG_ENABLE,
+                pending: pending == NVML_DEVICE_MIG_ENABLE,
+            })
+        }
+    }
+
+    pub fn max_mig_device_count(&self, device: &Device) -> Result<u32, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceGetMaxMigDeviceCount.as_ref())?;
+        unsafe {
+            let mut count: c_uint = 0;
+            nvml_try(sym(device.handle(), &mut count))?;
+            Ok(count)
+        }
+    }
+
+    /// Get the MIG device at `index` of a MIG-enabled parent device. Unpopulated indices return `NotFound`.
+    pub fn mig_device_by_index<'nvml>(&self, device: &Device<'nvml>, index: u32) -> Result<Device<'nvml>, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceGetMigDeviceHandleByIndex.as_ref())?;
+        unsafe {
+            let mut mig_device: nvmlDevice_t = std::ptr::null_mut();
+            nvml_try(sym(device.handle(), index, &mut mig_device))?;
+            Ok(Device::new(mig_device, device.nvml()))
+        }
+    }
+
+    pub fn gpu_instance_id(&self, mig_device: &Device) -> Result<u32, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceGetGpuInstanceId.as_ref())?;
+        unsafe {
+            let mut id: c_uint = 0;
+            nvml_try(sym(mig_device.handle(), &mut id))?;
+            Ok(id)
+        }
+    }
+
+    pub fn compute_instance_id(&self, mig_device: &Device) -> Result<u32, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceGetComputeInstanceId.as_ref())?;
+        unsafe {
+            let mut id: c_uint = 0;
+            nvml_try(sym(mig_device.handle(), &mut id))?;
+            Ok(id)
+        }
+    }
+
+    pub fn attributes(&self, device: &Device) -> Result<nvmlDeviceAttributes_t, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceGetAttributes_v2.as_ref())?;
+        unsafe {
+            let mut attributes: nvmlDeviceAttributes_t = std::mem::zeroed();
+            nvml_try(sym(device.handle(), &mut attributes))?;
+            Ok(attributes)
+        }
+    }
+
+    /// Whether the device is a vGPU host, i.e. runs the vGPU manager.
+    pub fn is_vgpu_host(&self, device: &Device) -> Result<bool, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceGetVirtualizationMode.as_ref())?;
+        unsafe {
+            let mut mode = 0;
+            nvml_try(sym(device.handle(), &mut mode))?;
+            Ok(mode == nvmlGpuVirtualizationMode_NVML_GPU_VIRTUALIZATION_MODE_HOST_VGPU)
+        }
+    }
+
+    pub fn active_vgpus(&self, device: &Device) -> Result<Vec<nvmlVgpuInstance_t>, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceGetActiveVgpus.as_ref())?;
+        unsafe { vgpu_list(sym, device) }
+    }
+
+    pub fn supported_vgpus(&self, device: &Device) -> Result<Vec<nvmlVgpuTypeId_t>, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceGetSupportedVgpus.as_ref())?;
+        unsafe { vgpu_list(sym, device) }
+    }
+
+    pub fn creatable_vgpus(&self, device: &Device) -> Result<Vec<nvmlVgpuTypeId_t>, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceGetCreatableVgpus.as_ref())?;
+        unsafe { vgpu_list(sym, device) }
+    }
+
+    pub fn vgpu_type_name(&self, type_id: nvmlVgpuTypeId_t) -> Result<String, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlVgpuTypeGetName.as_ref())?;
+        unsafe {
+            let mut buf = vec![0 as c_char; NVML_VGPU_NAME_BUFFER_SIZE as usize];
+            let mut size = NVML_VGPU_NAME_BUFFER_SIZE;
+            nvml_try(sym(type_id, buf.as_mut_ptr(), &mut size))?;
+            Ok(string_from_buf(&buf))
+        }
+    }
+
+    pub fn vgpu_instance_type(&self, instance: nvmlVgpuInstance_t) -> Result<nvmlVgpuTypeId_t, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlVgpuInstanceGetType.as_ref())?;
+        unsafe {
+            let mut type_id: nvmlVgpuTypeId_t = 0;
+            nvml_try(sym(instance, &mut type_id))?;
+            Ok(type_id)
+        }
+    }
+
+    pub fn vgpu_instance_uuid(&self, instance: nvmlVgpuInstance_t) -> Result<String, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlVgpuInstanceGetUUID.as_ref())?;
+        unsafe {
+            let mut buf = vec![0 as c_char; NVML_DEVICE_UUID_BUFFER_SIZE as usize];
+            nvml_try(sym(instance, buf.as_mut_ptr(), NVML_DEVICE_UUID_BUFFER_SIZE))?;
+            Ok(string_from_buf(&buf))
+        }
+    }
+
+    pub fn vgpu_instance_vm_id(&self, instance: nvmlVgpuInstance_t) -> Result<String, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlVgpuInstanceGetVmID.as_ref())?;
+        unsafe {
+            let mut buf = vec![0 as c_char; VM_ID_BUFFER_SIZE as usize];
+            let mut id_type: nvmlVgpuVmIdType_t = 0;
+            nvml_try(sym(instance, buf.as_mut_ptr(), VM_ID_BUFFER_SIZE, &mut id_type))?;
+            Ok(string_from_buf(&buf))
+        }
+    }
+
+    pub fn vgpu_instance_fb_usage(&self, instance: nvmlVgpuInstance_t) -> Result<u64, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlVgpuInstanceGetFbUsage.as_ref())?;
+        unsafe {
+            let mut usage: c_ulonglong = 0;
+            nvml_try(sym(instance, &mut usage))?;
+            Ok(usage)
+        }
+    }
+
+    pub fn vgpu_instance_encoder_stats(&self, instance: nvmlVgpuInstance_t) -> Result<EncoderStats, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlVgpuInstanceGetEncoderStats.as_ref())?;
+        unsafe {
+            let mut session_count: c_uint = 0;
+            let mut average_fps: c_uint = 0;
+            let mut average_latency: c_uint = 0;
+            nvml_try(sym(instance, &mut session_count, &mut average_fps, &mut average_latency))?;
+            Ok(EncoderStats { session_count, average_fps, average_latency })
+        }
+    }
+
+    pub fn vgpu_instance_fbc_stats(&self, instance: nvmlVgpuInstance_t) -> Result<FbcStats, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlVgpuInstanceGetFBCStats.as_ref())?;
+        unsafe {
+            let mut stats: nvmlFBCStats_t = std::mem::zeroed();
+            nvml_try(sym(instance, &mut stats))?;
+            Ok(stats.into())
+        }
+    }
+
+    pub fn vgpu_instance_is_licensed(&self, instance: nvmlVgpuInstance_t) -> Result<bool, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlVgpuInstanceGetLicenseStatus.as_ref())?;
+        unsafe {
+            let mut licensed: c_uint = 0;
+            nvml_try(sym(instance, &mut licensed))?;
+            Ok(licensed != 0)
+        }
+    }
+
+    pub fn remapped_rows(&self, device: &Device) -> Result<RemappedRows, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceGetRemappedRows.as_ref())?;
+        unsafe {
+            let mut correctable: c_uint = 0;
+            let mut uncorrectable: c_uint = 0;
+            let mut pending: c_uint = 0;
+            let mut failure: c_uint = 0;
+            nvml_try(sym(device.handle(), &mut correctable, &mut uncorrectable, &mut pending, &mut failure))?;
+            Ok(RemappedRows {
+                correctable,
+                uncorrectable,
+                pending: pending != 0,
+                failure: failure != 0,
+            })
+        }
+    }
+
+    /// Number of memory banks per remaining row remapping availability bucket.
+    pub fn row_remapper_histogram(&self, device: &Device) -> Result<nvmlRowRemapperHistogramValues_t, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceGetRowRemapperHistogram.as_ref())?;
+        unsafe {
+            let mut values: nvmlRowRemapperHistogramValues_t = std::mem::zeroed();
+            nvml_try(sym(device.handle(), &mut values))?;
+            Ok(values)
+        }
+    }
+
+    /// Framebuffer memory info including memory reserved by the driver, supported by drivers from R510.
+    pub fn memory_info_v2(&self, device: &Device) -> Result<nvmlMemory_v2_t, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceGetMemoryInfo_v2.as_ref())?;
+        unsafe {
+            let mut memory: nvmlMemory_v2_t = std::mem::zeroed();
+            memory.version = MEMORY_V2;
+            nvml_try(sym(device.handle(), &mut memory))?;
+            Ok(memory)
+        }
+    }
+
+    /// Bitmasks of the NUMA nodes closest to a device, sized to `size` like `Device::cpu_affinity()`.
+    #[cfg(target_os = "linux")]
+    pub fn memory_affinity(&self, device: &Device, size: usize) -> Result<Vec<c_ulong>, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceGetMemoryAffinity.as_ref())?;
+        unsafe {
+            let mut node_set: Vec<c_ulong> = vec![0; size];
+            nvml_try(sym(device.handle(), size as c_uint, node_set.as_mut_ptr(), NVML_AFFINITY_SCOPE_NODE))?;
+            Ok(node_set)
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn p2p_status(&self, device: &Device, peer: &Device, capability: nvmlGpuP2PCapsIndex_t) -> Result<P2pStatus, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceGetP2PStatus.as_ref())?;
+        unsafe {
+            let mut status: nvmlGpuP2PStatus_t = 0;
+            nvml_try(sym(device.handle(), peer.handle(), capability, &mut status))?;
+            P2pStatus::try_from(status)
+        }
+    }
+
+    /// Like `Device::field_values_for()`, with a scope ID (e.g. an NVLink) for each field ID.
+    pub fn field_values_for(&self, device: &Device, fields: &[(u32, u32)]) -> Result<Vec<Result<SampleValue, NvmlError>>, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlDeviceGetFieldValues.as_ref())?;
+        unsafe {
+            let mut values = fields
+                .iter()
+                .map(|(id, scope)| {
+                    let mut value: nvmlFieldValue_t = std::mem::zeroed();
+                    value.fieldId = *id;
+                    value.scopeId = *scope;
+                    value
+                })
+                .collect::<Vec<_>>();
+            nvml_try(sym(device.handle(), values.len() as i32, values.as_mut_ptr()))?;
+            Ok(values
+                .into_iter()
+                .map(|value| {
+                    nvml_try(value.nvmlReturn)?;
+                    Ok(SampleValue::from_tag_and_union(&SampleValueType::try_from(value.valueType)?, value.value))
+                })
+                .collect())
+        }
+    }
+
+    pub fn gpm_supported(&self, device: &Device) -> Result<bool, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlGpmQueryDeviceSupport.as_ref())?;
+        unsafe {
+            let mut support: nvmlGpmSupport_t = std::mem::zeroed();
+            support.version = NVML_GPM_SUPPORT_VERSION;
+            nvml_try(sym(device.handle(), &mut support))?;
+            Ok(support.isSupportedDevice != 0)
+        }
+    }
+
+    fn gpm_sample_alloc(&self) -> Result<GpmSample, NvmlError> {
+        let alloc = nvml_sym(self.lib.nvmlGpmSampleAlloc.as_ref())?;
+        let free = *nvml_sym(self.lib.nvmlGpmSampleFree.as_ref())?;
+        unsafe {
+            let mut sample: nvmlGpmSample_t = std::ptr::null_mut();
+            nvml_try(alloc(&mut sample))?;
+            Ok(GpmSample { sample, free })
+        }
+    }
+
+    pub fn gpm_sample(&self, device: &Device) -> Result<GpmSample, NvmlError> {
+        let get = nvml_sym(self.lib.nvmlGpmSampleGet.as_ref())?;
+        let sample = self.gpm_sample_alloc()?;
+        unsafe {
+            nvml_try(get(device.handle(), sample.sample))?;
+        }
+        Ok(sample)
+    }
+
+    /// GPM sample of one GPU instance of a MIG-enabled device.
+    pub fn gpm_mig_sample(&self, device: &Device, gpu_instance_id: u32) -> Result<GpmSample, NvmlError> {
+        let get = nvml_sym(self.gpm_mig_sample_get.as_ref())?;
+        let sample = self.gpm_sample_alloc()?;
+        unsafe {
+            nvml_try(get(device.handle(), gpu_instance_id, sample.sample))?;
+        }
+        Ok(sample)
+    }
+
+    /// Compute GPM metrics over the period between two samples of the same device.
+    pub fn gpm_metrics(&self, previous: &GpmSample, current: &GpmSample, ids: &[nvmlGpmMetricId_t]) -> Result<Vec<Result<f64, NvmlError>>, NvmlError> {
+        let sym = nvml_sym(self.lib.nvmlGpmMetricsGet.as_ref())?;
+        unsafe {
+            let mut metrics: nvmlGpmMetricsGet_t = std::mem::zeroed();
+            if ids.len() > metrics.metrics.len() {
+                return Err(NvmlError::InsufficientSize(Some(metrics.metrics.len())));
+            }
+            metrics.version = NVML_GPM_METRICS_GET_VERSION;
+            metrics.numMetrics = ids.len() as c_uint;
+            metrics.sample1 = previous.sample;
+            metrics.sample2 = current.sample;
+            for (metric, id) in metrics.metrics.iter_mut().zip(ids) {
+                metric.metricId = *id;
+            }
+            nvml_try(sym(&mut metrics))?;
+            Ok(metrics.metrics[..ids.len()].iter().map(|metric| nvml_try(metric.nvmlReturn).map(|_| metric.value)).collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buf(bytes: &[u8], size: usize) -> Vec<c_char> {
+        let mut buf = vec![0 as c_char; size];
+        for (c, b) in buf.iter_mut().zip(bytes) {
+            *c = *b as c_char;
+        }
+        buf
+    }
+
+    #[test]
+    fn reads_strings_up_to_nul() {
+        assert_eq!(string_from_buf(&buf(b"GRID A100-4C", NVML_VGPU_NAME_BUFFER_SIZE as usize)), "GRID A100-4C");
+        // VM IDs are shorter than the buffer, which may hold leftovers after the terminator
+        assert_eq!(string_from_buf(&buf(b"1234\0stale", VM_ID_BUFFER_SIZE as usize)), "1234");
+        assert_eq!(string_from_buf(&buf(b"", VM_ID_BUFFER_SIZE as usize)), "");
+        assert_eq!(string_from_buf(&buf(b"vm-\xff", VM_ID_BUFFER_SIZE as usize)), "vm-\u{fffd}");
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::bail;
+use anyhow::Context as _;
+use log::trace;
+use log::warn;
+use nvml::enums::device::SampleValue;
+use nvml::Device;
+use prometheus::register_counter_vec;
+use prometheus::register_gauge_vec;
+use prometheus::CounterVec;
+use prometheus::GaugeVec;
+use serde::Deserialize;
+
+use crate::cumulative::Cumulative;
+use crate::ffi::RawNvml;
+
+/// Field values to export, loaded from the file passed to `--field-values-config`.
+#[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    fields: Vec<FieldConfig>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FieldConfig {
+    /// `NVML_FI_*` field ID.
+    id: u32,
+    /// Scope ID, e.g. the NVLink for per-link fields, exported as the `scope` label.
+    scope: Option<u32>,
+    name: String,
+    help: String,
+    #[serde(default, rename = "type")]
+    kind: Kind,
+    /// Factor applied to the raw value, e.g. 0.001 to export milliwatts as watts.
+    #[serde(default = "default_scale")]
+    scale: f64,
+    /// Unit appended to the metric name unless it already ends with it.
+    unit: Option<String>,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    #[default]
+    Gauge,
+    Counter,
+}
+
+fn default_scale() -> f64 {
+    1.
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(path).with_context(|| format!("could not open {}", path.display()))?;
+        serde_yaml::from_reader(file).with_context(|| format!("could not parse {}", path.display()))
+    }
+}
+
+impl FieldConfig {
+    /// Metric name following the Prometheus conventions, e.g. `nvml_nvlink_tx_bytes_total`.
+    fn metric_name(&self) -> String {
+        let counter = self.kind == Kind::Counter;
+        let mut name = match self.name.strip_suffix("_total") {
+            Some(name) if counter => name.to_string(),
+            _ => self.name.clone(),
+        };
+        if let Some(unit) = &self.unit {
+            if !name.ends_with(&format!("_{unit}")) {
+                name = format!("{name}_{unit}");
+            }
+        }
+        if counter {
+            name.push_str("_total");
+        }
+        name
+    }
+}
+
+#[derive(Clone)]
+enum Series {
+    Gauge(GaugeVec),
+    Counter(CounterVec),
+}
+
+struct Field {
+    id: u32,
+    scope: Option<u32>,
+    scale: f64,
+    series: Series,
+}
+
+/// Metrics registered for the configured field values.
+#[derive(Default)]
+pub struct FieldValues {
+    fields: Vec<Field>,
+}
+
+impl FieldValues {
+    /// Register one metric per configured name. Fields sharing a name, e.g. the same field for
+    /// several NVLinks, are exported as series of the same metric.
+    pub fn register(config: &Config) -> anyhow::Result<Self> {
+        let mut registered: HashMap<String, (Kind, bool, Series)> = HashMap::new();
+        let mut fields = Vec::with_capacity(config.fields.len());
+        for field in &config.fields {
+            let name = field.metric_name();
+            let scoped = field.scope.is_some();
+            let series = match registered.get(&name) {
+                Some((kind, was_scoped, series)) => {
+                    if *kind != field.kind || *was_scoped != scoped {
+                        bail!("field {} is exported as {}, which other fields export with a different type or scope", field.id, name);
+                    }
+                    series.clone()
+                }
+                None => {
+                    let labels: &[&str] = if scoped { &["device", "uuid", "scope"] } else { &["device", "uuid"] };
+                    let series = match field.kind {
+                        Kind::Gauge => register_gauge_vec!(name.as_str(), field.help.as_str(), labels).map(Series::Gauge),
+                        Kind::Counter => register_counter_vec!(name.as_str(), field.help.as_str(), labels).map(Series::Counter),
+                    }
+                    .with_context(|| format!("field {} cannot be exported as {}", field.id, name))?;
+                    registered.insert(name, (field.kind, scoped, series.clone()));
+                    series
+                }
+            };
+            fields.push(Field {
+                id: field.id,
+                scope: field.scope,
+                scale: field.scale,
+                series,
+            });
+        }
+        Ok(FieldValues { fields })
+    }
+
+    /// Query all configured fields of `device` in a single call.
+    pub fn collect(&self, raw: &RawNvml, cumulative: &Cumulative, device: &Device, dl: &[&str; 2]) {
+        if self.fields.is_empty() {
+            return;
+        }
+
+        let ids = self.fields.iter().map(|field| (field.id, field.scope.unwrap_or(0))).collect::<Vec<_>>();
+        let samples = match raw.field_values_for(device, &ids) {
+            Ok(samples) => samples,
+            Err(e) => {
+                warn!("error fetching field values: {:?}", e);
+                return;
+            }
+        };
+
+        for (field, sample) in self.fields.iter().zip(samples) {
+            let value = match sample {
+                Ok(SampleValue::F64(v)) => v,
+                Ok(SampleValue::U32(v)) => v as f64,
+                Ok(SampleValue::U64(v)) => v as f64,
+                Ok(SampleValue::I64(v)) => v as f64,
+                Err(e) => {
+                    trace!("failed to collect field {}: {:?}", field.id, e);
+                    continue;
+                }
+            };
+            let scope_string = field.scope.map(|scope| scope.to_string());
+            let labels = match &scope_string {
+                Some(scope) => vec![dl[0], dl[1], scope.as_str()],
+                None => vec![dl[0], dl[1]],
+            };
+            match &field.series {
+                Series::Gauge(gauge) => {
+                    set_gv!(gauge, &labels, value * field.scale);
+                }
+                Series::Counter(counter) => cumulative.observe(counter, &labels, value * field.scale),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> anyhow::Result<Config> {
+        Ok(serde_yaml::from_str(yaml)?)
+    }
+
+    #[test]
+    fn parses_config() {
+        let config = parse("fields:\n  - {id: 155, name: nvml_power_usage_instant, help: instant power, scale: 0.001, unit: watts}\n  - {id: 138, scope: 1, name: nvml_nvlink_tx, help: NVLink TX, type: counter, unit: bytes}\n").unwrap();
+        let (power, nvlink) = (&config.fields[0], &config.fields[1]);
+        assert_eq!((power.id, power.scope, power.kind == Kind::Gauge, power.scale), (155, None, true, 0.001));
+        assert_eq!((nvlink.id, nvlink.scope, nvlink.kind == Kind::Counter, nvlink.scale), (138, Some(1), true, 1.));
+
+        assert!(parse("").unwrap().fields.is_empty());
+        assert!(parse("fields:\n  - {id: 155, name: x, help: x, type: histogram}\n").is_err());
+        assert!(parse("fields:\n  - {id: 155, name: x, help: x, scal: 0.001}\n").is_err());
+        assert!(parse("fields:\n  - {name: x, help: x}\n").is_err());
+    }
+
+    #[test]
+    fn names_metrics() {
+        let field = |name: &str, kind, unit: Option<&str>| FieldConfig {
+            id: 0,
+            scope: None,
+            name: name.to_string(),
+            help: String::new(),
+            kind,
+            scale: 1.,
+            unit: unit.map(str::to_string),
+        };
+        assert_eq!(field("nvml_power", Kind::Gauge, None).metric_name(), "nvml_power");
+        assert_eq!(field("nvml_power", Kind::Gauge, Some("watts")).metric_name(), "nvml_power_watts");
+        assert_eq!(field("nvml_power_watts", Kind::Gauge, Some("watts")).metric_name(), "nvml_power_watts");
+        assert_eq!(field("nvml_nvlink_tx", Kind::Counter, Some("bytes")).metric_name(), "nvml_nvlink_tx_bytes_total");
+        assert_eq!(field("nvml_nvlink_tx_bytes_total", Kind::Counter, Some("bytes")).metric_name(), "nvml_nvlink_tx_bytes_total");
+        assert_eq!(field("nvml_energy_total", Kind::Counter, None).metric_name(), "nvml_energy_total");
+    }
+
+    #[test]
+    fn rejects_conflicting_names() {
+        crate::test_metrics();
+        let builtin = parse("fields:\n  - {id: 155, name: nvml_power_usage, help: collides with a built-in metric}\n").unwrap();
+        let e = FieldValues::register(&builtin).err().unwrap();
+        assert!(format!("{:#}", e).contains("nvml_power_usage"), "{:#}", e);
+
+        let mixed = parse("fields:\n  - {id: 1, name: nvml_test_mixed, help: x}\n  - {id: 2, scope: 0, name: nvml_test_mixed, help: x}\n").unwrap();
+        assert!(FieldValues::register(&mixed).is_err());
+    }
+}
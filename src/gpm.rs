@@ -0,0 +1,204 @@
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use log::debug;
+use log::trace;
+use log::warn;
+use nvml::error::NvmlError;
+use nvml::Device;
+use nvml_wrapper_sys::bindings::nvmlGpmMetricId_t;
+use nvml_wrapper_sys::bindings::nvmlGpmMetricId_t_NVML_GPM_METRIC_ANY_TENSOR_UTIL;
+use nvml_wrapper_sys::bindings::nvmlGpmMetricId_t_NVML_GPM_METRIC_DRAM_BW_UTIL;
+use nvml_wrapper_sys::bindings::nvmlGpmMetricId_t_NVML_GPM_METRIC_FP16_UTIL;
+use nvml_wrapper_sys::bindings::nvmlGpmMetricId_t_NVML_GPM_METRIC_FP32_UTIL;
+use nvml_wrapper_sys::bindings::nvmlGpmMetricId_t_NVML_GPM_METRIC_FP64_UTIL;
+use nvml_wrapper_sys::bindings::nvmlGpmMetricId_t_NVML_GPM_METRIC_NVLINK_TOTAL_RX_PER_SEC;
+use nvml_wrapper_sys::bindings::nvmlGpmMetricId_t_NVML_GPM_METRIC_NVLINK_TOTAL_TX_PER_SEC;
+use nvml_wrapper_sys::bindings::nvmlGpmMetricId_t_NVML_GPM_METRIC_PCIE_RX_PER_SEC;
+use nvml_wrapper_sys::bindings::nvmlGpmMetricId_t_NVML_GPM_METRIC_PCIE_TX_PER_SEC;
+use nvml_wrapper_sys::bindings::nvmlGpmMetricId_t_NVML_GPM_METRIC_SM_OCCUPANCY;
+use nvml_wrapper_sys::bindings::nvmlGpmMetricId_t_NVML_GPM_METRIC_SM_UTIL;
+
+use crate::ffi::GpmSample;
+use crate::ffi::RawNvml;
+use crate::Metrics;
+
+/// Bandwidth metrics are reported in MiB/s.
+const MIB: f64 = 1024. * 1024.;
+
+const METRIC_IDS: [nvmlGpmMetricId_t; 11] = [
+    nvmlGpmMetricId_t_NVML_GPM_METRIC_SM_UTIL,
+    nvmlGpmMetricId_t_NVML_GPM_METRIC_SM_OCCUPANCY,
+    nvmlGpmMetricId_t_NVML_GPM_METRIC_ANY_TENSOR_UTIL,
+    nvmlGpmMetricId_t_NVML_GPM_METRIC_DRAM_BW_UTIL,
+    nvmlGpmMetricId_t_NVML_GPM_METRIC_FP16_UTIL,
+    nvmlGpmMetricId_t_NVML_GPM_METRIC_FP32_UTIL,
+    nvmlGpmMetricId_t_NVML_GPM_METRIC_FP64_UTIL,
+    nvmlGpmMetricId_t_NVML_GPM_METRIC_PCIE_TX_PER_SEC,
+    nvmlGpmMetricId_t_NVML_GPM_METRIC_PCIE_RX_PER_SEC,
+    nvmlGpmMetricId_t_NVML_GPM_METRIC_NVLINK_TOTAL_TX_PER_SEC,
+    nvmlGpmMetricId_t_NVML_GPM_METRIC_NVLINK_TOTAL_RX_PER_SEC,
+];
+
+/// Metrics that NVML computes per GPU instance of a MIG-enabled device.
+const MIG_METRIC_IDS: [nvmlGpmMetricId_t; 4] = [
+    nvmlGpmMetricId_t_NVML_GPM_METRIC_SM_UTIL,
+    nvmlGpmMetricId_t_NVML_GPM_METRIC_SM_OCCUPANCY,
+    nvmlGpmMetricId_t_NVML_GPM_METRIC_ANY_TENSOR_UTIL,
+    nvmlGpmMetricId_t_NVML_GPM_METRIC_DRAM_BW_UTIL,
+];
+
+/// Previous GPM sample per device UUID, `None` for devices without GPM support.
+///
+/// GPU instances of MIG-enabled devices are keyed by `<uuid>/<gpu instance ID>`.
+#[derive(Default)]
+pub struct Samples(Mutex<HashMap<String, Option<GpmSample>>>);
+
+/// Export GPM metrics over the period since the previous collection, starting from the second collection.
+pub fn collect(metrics: &Metrics, raw: &RawNvml, samples: &Samples, device: &Device, dl: &[&str; 2]) {
+    let mut samples = samples.0.lock().unwrap();
+    match samples.get(dl[1]) {
+        Some(None) => return,
+        Some(Some(_)) => (),
+        None => match raw.gpm_supported(device) {
+            Ok(true) => (),
+            Ok(false) | Err(NvmlError::NotSupported) | Err(NvmlError::FailedToLoadSymbol(_)) => {
+                debug!("GPM is not supported on device {}, disabling GPM metrics", dl[0]);
+                samples.insert(dl[1].to_string(), None);
+                return;
+            }
+            Err(e) => {
+                warn!("could not check GPM support, skipping GPM metrics: {:?}", e);
+                return;
+            }
+        },
+    }
+
+    let current = match raw.gpm_sample(device) {
+        Ok(sample) => sample,
+        Err(e) => {
+            warn!("error taking GPM sample: {:?}", e);
+            return;
+        }
+    };
+    if let Some(Some(previous)) = samples.get(dl[1]) {
+        match raw.gpm_metrics(previous, &current, &METRIC_IDS) {
+            Ok(values) => {
+                for (id, value) in METRIC_IDS.iter().zip(values) {
+                    match value {
+                        Ok(value) => set(metrics, *id, dl, value),
+                        Err(e) => trace!("failed to compute GPM metric {}: {:?}", id, e),
+                    }
+                }
+            }
+            Err(e) => warn!("error computing GPM metrics: {:?}", e),
+        }
+    }
+    samples.insert(dl[1].to_string(), Some(current));
+}
+
+/// Export GPM metrics of each GPU instance of a MIG-enabled device, from the second collection on.
+///
+/// Runs after [`collect`], which disables GPM on devices that do not support it.
+pub fn collect_mig(metrics: &Metrics, raw: &RawNvml, samples: &Samples, device: &Device, dl: &[&str; 2], gpu_instance_ids: &BTreeSet<u32>) {
+    let mut samples = samples.0.lock().unwrap();
+    if let Some(None) = samples.get(dl[1]) {
+        return;
+    }
+    // GPU instances can be destroyed at runtime
+    let prefix = format!("{}/", dl[1]);
+    samples.retain(|key, _| match key.strip_prefix(&prefix) {
+        Some(gi_id) => gi_id.parse().is_ok_and(|gi_id| gpu_instance_ids.contains(&gi_id)),
+        None => true,
+    });
+
+    for gpu_instance_id in gpu_instance_ids {
+        let current = match raw.gpm_mig_sample(device, *gpu_instance_id) {
+            Ok(sample) => sample,
+            Err(NvmlError::NotSupported) | Err(NvmlError::FailedToLoadSymbol(_)) => {
+                trace!("per GPU instance GPM is not supported by device {} or its driver", dl[0]);
+                return;
+            }
+            Err(e) => {
+                warn!("error taking GPM sample of GPU instance {} of device {}: {:?}", gpu_instance_id, dl[0], e);
+                continue;
+            }
+        };
+        let gi_id = gpu_instance_id.to_string();
+        let key = format!("{}{}", prefix, gi_id);
+        if let Some(Some(previous)) = samples.get(&key) {
+            match raw.gpm_metrics(previous, &current, &MIG_METRIC_IDS) {
+                Ok(values) => {
+                    for (id, value) in MIG_METRIC_IDS.iter().zip(values) {
+                        match value {
+                            Ok(value) => set_mig(metrics, *id, &[dl[0], dl[1], gi_id.as_str()], value),
+                            Err(e) => trace!("failed to compute GPM metric {} of GPU instance {}: {:?}", id, gi_id, e),
+                        }
+                    }
+                }
+                Err(e) => warn!("error computing GPM metrics of GPU instance {}: {:?}", gi_id, e),
+            }
+        }
+        samples.insert(key, Some(current));
+    }
+}
+
+#[allow(non_upper_case_globals)]
+fn set_mig(metrics: &Metrics, id: nvmlGpmMetricId_t, gil: &[&str; 3], value: f64) {
+    match id {
+        nvmlGpmMetricId_t_NVML_GPM_METRIC_SM_UTIL => {
+            set_gv!(metrics.gv_mig_gpm_sm_activity, gil, value);
+        }
+        nvmlGpmMetricId_t_NVML_GPM_METRIC_SM_OCCUPANCY => {
+            set_gv!(metrics.gv_mig_gpm_sm_occupancy, gil, value);
+        }
+        nvmlGpmMetricId_t_NVML_GPM_METRIC_ANY_TENSOR_UTIL => {
+            set_gv!(metrics.gv_mig_gpm_tensor_activity, gil, value);
+        }
+        nvmlGpmMetricId_t_NVML_GPM_METRIC_DRAM_BW_UTIL => {
+            set_gv!(metrics.gv_mig_gpm_dram_bandwidth_utilization, gil, value);
+        }
+        _ => (),
+    }
+}
+
+#[allow(non_upper_case_globals)]
+fn set(metrics: &Metrics, id: nvmlGpmMetricId_t, dl: &[&str; 2], value: f64) {
+    match id {
+        nvmlGpmMetricId_t_NVML_GPM_METRIC_SM_UTIL => {
+            set_gv!(metrics.gv_gpm_sm_activity, dl, value);
+        }
+        nvmlGpmMetricId_t_NVML_GPM_METRIC_SM_OCCUPANCY => {
+            set_gv!(metrics.gv_gpm_sm_occupancy, dl, value);
+        }
+        nvmlGpmMetricId_t_NVML_GPM_METRIC_ANY_TENSOR_UTIL => {
+            set_gv!(metrics.gv_gpm_tensor_activity, dl, value);
+        }
+        nvmlGpmMetricId_t_NVML_GPM_METRIC_DRAM_BW_UTIL => {
+            set_gv!(metrics.gv_gpm_dram_bandwidth_utilization, dl, value);
+        }
+        nvmlGpmMetricId_t_NVML_GPM_METRIC_FP16_UTIL => {
+            set_gv!(metrics.gv_gpm_pipe_activity, &[dl[0], dl[1], "fp16"], value);
+        }
+        nvmlGpmMetricId_t_NVML_GPM_METRIC_FP32_UTIL => {
+            set_gv!(metrics.gv_gpm_pipe_activity, &[dl[0], dl[1], "fp32"], value);
+        }
+        nvmlGpmMetricId_t_NVML_GPM_METRIC_FP64_UTIL => {
+            set_gv!(metrics.gv_gpm_pipe_activity, &[dl[0], dl[1], "fp64"], value);
+        }
+        nvmlGpmMetricId_t_NVML_GPM_METRIC_PCIE_TX_PER_SEC => {
+            set_gv!(metrics.gv_gpm_pcie_bandwidth, &[dl[0], dl[1], "tx"], value * MIB);
+        }
+        nvmlGpmMetricId_t_NVML_GPM_METRIC_PCIE_RX_PER_SEC => {
+            set_gv!(metrics.gv_gpm_pcie_bandwidth, &[dl[0], dl[1], "rx"], value * MIB);
+        }
+        nvmlGpmMetricId_t_NVML_GPM_METRIC_NVLINK_TOTAL_TX_PER_SEC => {
+            set_gv!(metrics.gv_gpm_nvlink_bandwidth, &[dl[0], dl[1], "tx"], value * MIB);
+        }
+        nvmlGpmMetricId_t_NVML_GPM_METRIC_NVLINK_TOTAL_RX_PER_SEC => {
+            set_gv!(metrics.gv_gpm_nvlink_bandwidth, &[dl[0], dl[1], "rx"], value * MIB);
+        }
+        _ => (),
+    }
+}
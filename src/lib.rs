@@ -1,49 +1,110 @@
 extern crate nvml_wrapper as nvml;
 
-use std::net::SocketAddr;
+use std::collections::HashSet;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::string::ToString;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Mutex;
+#[cfg(target_os = "linux")]
+use std::time::Instant;
 use std::time::SystemTime;
 
+#[cfg(target_os = "linux")]
+use anyhow::Context as _;
+use clap::parser::ValueSource;
 use clap::Arg;
 use clap::ArgAction;
 use clap::Command;
 use futures::channel::oneshot;
 use futures::channel::oneshot::Receiver;
 use futures::channel::oneshot::Sender;
+use futures::FutureExt;
 use log::debug;
 use log::error;
 use log::info;
 use log::trace;
 use log::warn;
 use nvml::bitmasks::device::ThrottleReasons;
-use nvml::enum_wrappers::device::Clock;
-use nvml::enum_wrappers::device::ClockId;
+use nvml::enum_wrappers::device::ComputeMode;
+#[cfg(target_os = "windows")]
+use nvml::enum_wrappers::device::DriverModel;
 use nvml::enum_wrappers::device::EccCounter;
 use nvml::enum_wrappers::device::EncoderType;
 use nvml::enum_wrappers::device::MemoryError;
 use nvml::enum_wrappers::device::MemoryLocation;
+use nvml::enum_wrappers::device::OperationMode;
+use nvml::enum_wrappers::device::PerformancePolicy;
+use nvml::enum_wrappers::device::RetirementCause;
 use nvml::enum_wrappers::device::TemperatureSensor;
 use nvml::error::NvmlError;
 use nvml::Nvml;
-use prometheus::default_registry;
+use prometheus::register_counter_vec;
 use prometheus::register_gauge;
 use prometheus::register_gauge_vec;
-use prometheus::Encoder;
+use prometheus::CounterVec;
 use prometheus::Gauge;
 use prometheus::GaugeVec;
-use prometheus::TextEncoder;
-use stderrlog;
 use tokio::task::JoinSet;
-use warp::Filter;
 
 use crate::str_helpers::*;
 
+macro_rules! set_gv {
+    ( $member:expr, $labels:expr, $val:expr ) => {
+        $member.with_label_values($labels).set($val as f64);
+        if cfg!(debug_assertions) {
+            log::trace!(stringify!($member));
+        }
+    };
+}
+
+#[cfg(debug_assertions)]
+macro_rules! timed {
+    ( $n:expr, $e:expr ) => {
+        let now = std::time::SystemTime::now();
+        let _ = $e;
+        log::trace!("{}: took {}ms", $n, now.elapsed().unwrap().as_millis());
+    };
+}
+#[cfg(not(debug_assertions))]
+macro_rules! timed {
+    ( $n:expr, $e:expr ) => {
+        $e
+    };
+}
+
+mod accounting;
+mod clocks;
+mod compression;
+mod cumulative;
+#[cfg(target_os = "linux")]
+mod events;
+mod exposition;
+mod ffi;
+mod field_values;
+mod gpm;
+mod listen;
+mod mig;
+mod process_utilization;
+mod sessions;
+mod slurm;
+mod snapshot;
 mod str_helpers;
+#[cfg(target_os = "linux")]
+mod systemd;
+#[cfg(target_os = "linux")]
+mod topology;
+mod units;
+mod vgpu;
+mod web;
+mod web_config;
+
+pub type Binds = Vec<(listen::ListenAddr, Receiver<()>)>;
 
-pub fn server_setup(args: Vec<String>) -> (Vec<(SocketAddr, Receiver<()>)>, Vec<Sender<()>>, Options) {
-    let matches = Command::new("nvml-exporter-rs")
+pub fn server_setup(args: Vec<String>) -> anyhow::Result<(Binds, Vec<Sender<()>>, Options)> {
+    let command = Command::new("nvml-exporter-rs")
         .version("0.0.1")
         .about("Prometheus exporter for NVIDIA GPU NVML metrics")
         .arg(
@@ -51,78 +112,239 @@ pub fn server_setup(args: Vec<String>) -> (Vec<(SocketAddr, Receiver<()>)>, Vec<
                 .short('l')
                 .long("listen")
                 .value_name("SOCKET_ADDRESS")
-                .help("listen address")
+                .help("listen address as IP:PORT or HOST:PORT, or unix:PATH for a Unix domain socket")
+                .value_parser(listen::ListenAddr::from_str)
                 .action(ArgAction::Append)
-                .default_values(&["[::]:9996", "0.0.0.0:9996"]),
+                .default_values(["[::]:9996", "0.0.0.0:9996"]),
+        )
+        .arg(
+            Arg::new("web.telemetry-path")
+                .long("web.telemetry-path")
+                .value_name("PATH")
+                .help("path under which to expose metrics")
+                .value_parser(|path: &str| if path.starts_with('/') { Ok(path.to_string()) } else { Err("must start with /") })
+                .default_value("/metrics"),
+        )
+        .arg(
+            Arg::new("web.config.file")
+                .long("web.config.file")
+                .value_name("FILE")
+                .help("exporter-toolkit compatible web config file enabling TLS and basic auth")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("web.compression-threshold")
+                .long("web.compression-threshold")
+                .value_name("BYTES")
+                .help("minimum size of metrics responses compressed for clients accepting gzip, deflate or zstd")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1024"),
         )
         .arg(Arg::new("throttle-reasons").long("throttle-reasons").action(ArgAction::SetTrue))
-        .arg(Arg::new("verbosity").short('v').action(ArgAction::Count))
-        .get_matches_from(args);
+        .arg(Arg::new("accounting").long("accounting").help("export stats of completed processes from NVML accounting mode").action(ArgAction::SetTrue))
+        .arg(Arg::new("process-utilization").long("process-utilization").help("export per-process utilization").action(ArgAction::SetTrue))
+        .arg(Arg::new("sessions").long("sessions").help("export per-session encoder and frame buffer capture metrics").action(ArgAction::SetTrue))
+        .arg(
+            Arg::new("max-sessions")
+                .long("max-sessions")
+                .value_name("COUNT")
+                .help("maximum number of sessions of each kind exported per device")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("16"),
+        )
+        .arg(Arg::new("supported-clocks").long("supported-clocks").help("export the supported memory and graphics clocks").action(ArgAction::SetTrue))
+        .arg(
+            Arg::new("field-values-config")
+                .long("field-values-config")
+                .value_name("FILE")
+                .help("YAML file mapping NVML field IDs to exported metrics")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(Arg::new("slurm-jobs").long("slurm-jobs").help("attribute GPU processes to Slurm jobs").action(ArgAction::SetTrue))
+        .arg(Arg::new("verbosity").short('v').action(ArgAction::Count));
+    #[cfg(unix)]
+    let command = command
+        .arg(
+            Arg::new("web.unix-socket-mode")
+                .long("web.unix-socket-mode")
+                .value_name("MODE")
+                .help("octal file mode of unix: listen sockets")
+                .value_parser(listen::parse_mode)
+                .default_value("0660"),
+        )
+        .arg(
+            Arg::new("web.unix-socket-owner")
+                .long("web.unix-socket-owner")
+                .value_name("USER[:GROUP]")
+                .help("owner of unix: listen sockets")
+                .value_parser(listen::Owner::from_str),
+        );
+    let matches = command.get_matches_from(args);
 
     let verbosity = matches.get_count("verbosity");
     stderrlog::new().module(module_path!()).module("nvml_exporter").verbosity(verbosity as usize).show_module_names(true).init().unwrap();
 
-    let addrs = matches.get_many::<String>("listen").unwrap().map(|val| val.to_string()).collect::<Vec<_>>();
+    #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
+    let mut addrs = matches.get_many::<listen::ListenAddr>("listen").unwrap().cloned().collect::<Vec<_>>();
+    #[cfg(target_os = "linux")]
+    {
+        let inherited = systemd::listen_fds();
+        if !inherited.is_empty() {
+            if matches.value_source("listen") == Some(ValueSource::CommandLine) {
+                warn!("ignoring --listen, serving on the sockets passed by systemd");
+            }
+            addrs = inherited;
+        }
+    }
 
     let mut senders: Vec<Sender<()>> = vec![];
 
     let binds = addrs
-        .iter()
+        .into_iter()
         .map(|addr| {
             let (s, r) = oneshot::channel::<()>();
             senders.push(s);
-            (SocketAddr::from_str(addr.clone().as_str()).unwrap(), r)
+            (addr, r)
         })
         .collect::<Vec<_>>();
 
     let opts = Options {
+        telemetry_path: matches.get_one::<String>("web.telemetry-path").unwrap().clone(),
+        web_config: match matches.get_one::<PathBuf>("web.config.file") {
+            Some(path) => Some(Arc::new(web_config::WebConfigFile::load(path).with_context(|| format!("invalid web config {}", path.display()))?)),
+            None => None,
+        },
+        compression_threshold: *matches.get_one::<usize>("web.compression-threshold").unwrap(),
+        #[cfg(unix)]
+        unix_socket: listen::UnixSocketOptions {
+            mode: matches.get_one::<u32>("web.unix-socket-mode").copied(),
+            owner: matches.get_one::<listen::Owner>("web.unix-socket-owner").cloned(),
+        },
         enable_throttle_reasons: matches.get_flag("throttle-reasons"),
+        enable_slurm_jobs: matches.get_flag("slurm-jobs"),
+        enable_accounting: matches.get_flag("accounting"),
+        enable_process_utilization: matches.get_flag("process-utilization"),
+        enable_sessions: matches.get_flag("sessions"),
+        max_sessions: *matches.get_one::<usize>("max-sessions").unwrap(),
+        enable_supported_clocks: matches.get_flag("supported-clocks"),
+        field_values: match matches.get_one::<PathBuf>("field-values-config") {
+            Some(path) => field_values::Config::load(path)?,
+            None => Default::default(),
+        },
     };
 
-    (binds, senders, opts)
+    Ok((binds, senders, opts))
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Options {
+    telemetry_path: String,
+    web_config: Option<Arc<web_config::WebConfigFile>>,
+    compression_threshold: usize,
+    #[cfg(unix)]
+    unix_socket: listen::UnixSocketOptions,
     enable_throttle_reasons: bool,
+    enable_slurm_jobs: bool,
+    enable_accounting: bool,
+    enable_process_utilization: bool,
+    enable_sessions: bool,
+    max_sessions: usize,
+    enable_supported_clocks: bool,
+    field_values: field_values::Config,
 }
 
 struct Context {
     metrics: Metrics,
+    /// Declared before `nvml` and `raw` so that samples are freed before NVML shuts down.
+    gpm_samples: gpm::Samples,
     nvml: Nvml,
+    raw: ffi::RawNvml,
     opts: Options,
+    accounting_seen: accounting::SeenProcesses,
+    slurm_user_names: slurm::UserNames,
+    process_utilization_last_seen: process_utilization::LastSeen,
+    cumulative: cumulative::Cumulative,
+    supported_clocks_collected: Mutex<HashSet<String>>,
+    field_values: field_values::FieldValues,
+    #[cfg(target_os = "linux")]
+    topology: topology::Topology,
+    /// Creation time of counters, for OpenMetrics `_created` samples.
+    created: exposition::Created,
+    compression: compression::Compression,
+    /// Set once a collection succeeded, to notify systemd of readiness once.
+    #[cfg(target_os = "linux")]
+    ready: AtomicBool,
+    /// Held across a collection and the gathering of its result, since collections reset series
+    /// that a concurrent scrape would otherwise miss.
+    collecting: Mutex<()>,
+    /// When a collection last succeeded, for `/readyz` and the systemd watchdog.
+    last_collection: Mutex<Option<Instant>>,
+    /// Set once all servers have shut down, to stop background collection.
+    shutdown: AtomicBool,
 }
 
-pub async fn serve(binds: Vec<(SocketAddr, Receiver<()>)>, opts: Options) {
+/// Serve metrics on every listener that could be set up, failing only if none could or if the
+/// metrics cannot be registered.
+pub async fn serve(binds: Binds, opts: Options) -> anyhow::Result<()> {
+    let metrics = Metrics::new().context("could not register metrics")?;
+    let field_values = field_values::FieldValues::register(&opts.field_values).context("could not register field value metrics")?;
+
+    let binds = binds.into_iter().map(|(addr, recv)| (addr, recv.shared())).collect();
+    let (listeners, errors) = listen::bind_all(
+        binds,
+        #[cfg(unix)]
+        &opts.unix_socket,
+    )
+    .await;
+    for e in errors.iter() {
+        error!("{}", e);
+    }
+    if listeners.is_empty() {
+        return Err(listen::Error::NoListeners.into());
+    }
+
     let ctx = Arc::new(Context {
-        metrics: Metrics::new().unwrap(),
-        nvml: Nvml::init().unwrap(),
-        opts: opts.clone(),
+        metrics,
+        gpm_samples: Default::default(),
+        nvml: Nvml::init().context("could not initialize NVML")?,
+        raw: ffi::RawNvml::load().context("could not load NVML")?,
+        field_values,
+        compression: compression::Compression::new(opts.compression_threshold),
+        opts,
+        accounting_seen: Default::default(),
+        slurm_user_names: Default::default(),
+        process_utilization_last_seen: Default::default(),
+        cumulative: Default::default(),
+        supported_clocks_collected: Default::default(),
+        #[cfg(target_os = "linux")]
+        topology: Default::default(),
+        created: Default::default(),
+        #[cfg(target_os = "linux")]
+        ready: AtomicBool::new(false),
+        collecting: Default::default(),
+        last_collection: Default::default(),
+        shutdown: AtomicBool::new(false),
     });
 
-    let mut set = JoinSet::new();
-    binds.into_iter().for_each(|(addr, recv)| {
-        let ctx = ctx.clone();
-        let routes = warp::any().map(move || {
-            let ctx = ctx.clone();
-            match gather(ctx) {
-                Ok(_) => (),
-                Err(e) => error!("error gathering metrics: {:?}", e),
-            }
-            let mut buffer = vec![];
-            let encoder = TextEncoder::new();
-            let metric_families = default_registry().gather();
-            encoder.encode(&metric_families, &mut buffer).unwrap();
+    // collect right away, so that readiness does not wait for the first scrape
+    let initial = ctx.clone();
+    tokio::task::spawn_blocking(move || web::collect(&initial));
 
-            String::from_utf8(buffer).unwrap()
-        });
+    #[cfg(target_os = "linux")]
+    let events = events::spawn(ctx.clone());
+    #[cfg(target_os = "linux")]
+    if let Some(timeout) = systemd::watchdog_timeout() {
+        tokio::spawn(systemd::watchdog(ctx.clone(), timeout));
+    }
 
-        let (addr, server) = warp::serve(routes).bind_with_graceful_shutdown(addr, async move {
-            recv.await.ok();
-            warn!("gracefully shutting down exporter on {}", addr.to_string());
-        });
+    let mut set = JoinSet::new();
+    listeners.into_iter().for_each(|(addr, listener, recv)| {
+        let handler = web::handler(warp::service(web::routes(ctx.clone())));
         info!("starting server on {}", addr);
-        set.spawn(server);
+        set.spawn(web::serve(listener, handler, ctx.opts.web_config.clone(), async move {
+            recv.await.ok();
+            warn!("gracefully shutting down exporter on {}", addr);
+        }));
     });
 
     let mut results: Vec<_> = Vec::with_capacity(set.len());
@@ -131,10 +353,18 @@ pub async fn serve(binds: Vec<(SocketAddr, Receiver<()>)>, opts: Options) {
     }
     for res in results.iter() {
         match res {
-            Ok(_) => (),
+            Ok(Ok(_)) => (),
+            Ok(Err(e)) => error!("server error: {}", e),
             Err(e) => error!("error during server shutdown: {}", e),
         }
     }
+
+    ctx.shutdown.store(true, Ordering::Relaxed);
+    #[cfg(target_os = "linux")]
+    if let Err(e) = tokio::task::spawn_blocking(move || events.join()).await {
+        error!("error stopping event listener: {}", e);
+    }
+    Ok(())
 }
 
 #[derive(Clone)]
@@ -156,21 +386,134 @@ struct Metrics {
     gv_utilization_memory: GaugeVec,
     gv_clock: GaugeVec,
     gv_applications_clock: GaugeVec,
+    gv_max_clock: GaugeVec,
+    gv_max_customer_boost_clock: GaugeVec,
+    gv_supported_memory_clock: GaugeVec,
+    gv_supported_graphics_clock: GaugeVec,
     gv_memory_info: GaugeVec,
     gv_display_active: GaugeVec,
     gv_display_mode: GaugeVec,
+    gv_encoder_capacity: GaugeVec,
     gv_encoder_capacity_h264: GaugeVec,
     gv_encoder_capacity_hevc: GaugeVec,
+    gv_encoder_utilization: GaugeVec,
+    gv_encoder_sampling_period: GaugeVec,
+    gv_decoder_utilization: GaugeVec,
+    gv_decoder_sampling_period: GaugeVec,
     gv_encoder_stats_sessions_count: GaugeVec,
     gv_encoder_stats_average_fps: GaugeVec,
     gv_encoder_stats_average_latency: GaugeVec,
     gv_current_clocks_throttle_reasons: GaugeVec,
+    cv_throttle_violation_seconds: CounterVec,
+    cv_energy: CounterVec,
+    cv_memory_errors: CounterVec,
     gv_memory_error_counters: GaugeVec,
+    gv_ecc_mode: GaugeVec,
+    gv_compute_mode: GaugeVec,
+    #[cfg(target_os = "linux")]
+    gv_persistence_mode: GaugeVec,
+    gv_auto_boost: GaugeVec,
+    gv_gpu_operation_mode: GaugeVec,
+    #[cfg(target_os = "windows")]
+    gv_driver_model: GaugeVec,
+    gv_retired_pages: GaugeVec,
+    gv_retired_pages_pending: GaugeVec,
+    gv_remapped_rows: GaugeVec,
+    gv_remapped_rows_pending: GaugeVec,
+    gv_remapped_rows_failure: GaugeVec,
+    gv_row_remapper_histogram: GaugeVec,
+    #[cfg(target_os = "linux")]
+    cv_xid_errors: CounterVec,
+    #[cfg(target_os = "linux")]
+    cv_xid_events: CounterVec,
+    #[cfg(target_os = "linux")]
+    gv_last_xid: GaugeVec,
+    #[cfg(target_os = "linux")]
+    gv_last_xid_timestamp: GaugeVec,
+    #[cfg(target_os = "linux")]
+    cv_ecc_error_events: CounterVec,
+    #[cfg(target_os = "linux")]
+    cv_clock_change_events: CounterVec,
+    gv_device_job_info: GaugeVec,
+    gv_job_memory_used: GaugeVec,
+    gv_accounting_enabled: GaugeVec,
+    cv_accounting_processes: CounterVec,
+    cv_accounting_runtime_seconds: CounterVec,
+    cv_accounting_gpu_seconds: CounterVec,
+    cv_accounting_max_memory_bytes: CounterVec,
+    gv_process_utilization_sm: GaugeVec,
+    gv_process_utilization_memory: GaugeVec,
+    gv_process_utilization_encoder: GaugeVec,
+    gv_process_utilization_decoder: GaugeVec,
+    gv_encoder_session_fps: GaugeVec,
+    gv_encoder_session_latency: GaugeVec,
+    gv_encoder_session_hres: GaugeVec,
+    gv_encoder_session_vres: GaugeVec,
+    gv_fbc_session_fps: GaugeVec,
+    gv_fbc_session_latency: GaugeVec,
+    gv_fbc_session_hres: GaugeVec,
+    gv_fbc_session_vres: GaugeVec,
+    gv_sessions_omitted: GaugeVec,
+    #[cfg(target_os = "linux")]
+    gv_numa_node: GaugeVec,
+    #[cfg(target_os = "linux")]
+    gv_cpu_affinity: GaugeVec,
+    #[cfg(target_os = "linux")]
+    gv_topology_link: GaugeVec,
+    #[cfg(target_os = "linux")]
+    gv_p2p_status: GaugeVec,
+    gv_gpm_sm_activity: GaugeVec,
+    gv_gpm_sm_occupancy: GaugeVec,
+    gv_gpm_tensor_activity: GaugeVec,
+    gv_gpm_dram_bandwidth_utilization: GaugeVec,
+    gv_gpm_pipe_activity: GaugeVec,
+    gv_gpm_pcie_bandwidth: GaugeVec,
+    gv_gpm_nvlink_bandwidth: GaugeVec,
+    gv_mig_gpm_sm_activity: GaugeVec,
+    gv_mig_gpm_sm_occupancy: GaugeVec,
+    gv_mig_gpm_tensor_activity: GaugeVec,
+    gv_mig_gpm_dram_bandwidth_utilization: GaugeVec,
+    gv_unit_info: GaugeVec,
+    gv_unit_temperature: GaugeVec,
+    gv_unit_psu_voltage: GaugeVec,
+    gv_unit_psu_current: GaugeVec,
+    gv_unit_psu_power_draw: GaugeVec,
+    gv_unit_psu_state: GaugeVec,
+    gv_unit_fan_speed: GaugeVec,
+    gv_unit_fan_failed: GaugeVec,
+    gv_unit_led_state: GaugeVec,
+    gv_mig_mode: GaugeVec,
+    gv_mig_device_info: GaugeVec,
+    gv_mig_memory_info: GaugeVec,
+    gv_mig_multiprocessor_count: GaugeVec,
+    gv_mig_gpu_instance_slice_count: GaugeVec,
+    gv_mig_compute_instance_slice_count: GaugeVec,
+    gv_mig_running_compute_processes_count: GaugeVec,
+    gv_vgpu_supported_types: GaugeVec,
+    gv_vgpu_creatable_types: GaugeVec,
+    gv_vgpu_active_count: GaugeVec,
+    gv_vgpu_info: GaugeVec,
+    gv_vgpu_fb_usage: GaugeVec,
+    gv_vgpu_encoder_stats_sessions_count: GaugeVec,
+    gv_vgpu_encoder_stats_average_fps: GaugeVec,
+    gv_vgpu_encoder_stats_average_latency: GaugeVec,
+    gv_vgpu_fbc_stats_sessions_count: GaugeVec,
+    gv_vgpu_fbc_stats_average_fps: GaugeVec,
+    gv_vgpu_fbc_stats_average_latency: GaugeVec,
+    gv_vgpu_licensed: GaugeVec,
 }
 
 impl Metrics {
     fn new() -> prometheus::Result<Metrics> {
         let dl = &["device", "uuid"];
+        let jl = &["device", "uuid", "job_id", "user", "partition"];
+        let pl = &["device", "uuid", "pid"];
+        let esl = &["device", "uuid", "session_id", "pid", "codec"];
+        let fsl = &["device", "uuid", "session_id", "pid", "type"];
+        let ml = &["device", "uuid", "mig_uuid", "gpu_instance_id", "compute_instance_id", "profile"];
+        let mml = &["device", "uuid", "mig_uuid", "gpu_instance_id", "compute_instance_id", "profile", "state"];
+        let gil = &["device", "uuid", "gpu_instance_id"];
+        let vl = &["device", "uuid", "vgpu_uuid"];
         Ok(Metrics {
             gv_exporter: register_gauge_vec!("nvml_exporter_info", "information about nvml-exporter itself", &["version"])?,
             g_device_count: register_gauge!("nvml_device_count", "number of nvml devices")?,
@@ -188,24 +531,173 @@ impl Metrics {
             gv_utilization_gpu: register_gauge_vec!("nvml_utilization_gpu", "GPU utilization", dl)?,
             gv_utilization_memory: register_gauge_vec!("nvml_utilization_memory", "memory utilization", dl)?,
             gv_clock: register_gauge_vec!("nvml_clock", "clock speed", &["device", "uuid", "clock_id", "type"])?,
-            gv_applications_clock: register_gauge_vec!("nvml_applications_clock", "clock speed", &["device", "uuid", "clock_id", "type"])?,
-            gv_memory_info: register_gauge_vec!("nvml_memory_info", "memory information", &["device", "uuid", "state"])?,
+            gv_applications_clock: register_gauge_vec!("nvml_applications_clock", "applications clock speed", &["device", "uuid", "clock_id", "type"])?,
+            gv_max_clock: register_gauge_vec!("nvml_max_clock", "max clock speed", &["device", "uuid", "type"])?,
+            gv_max_customer_boost_clock: register_gauge_vec!("nvml_max_customer_boost_clock", "max customer boost clock speed", &["device", "uuid", "type"])?,
+            gv_supported_memory_clock: register_gauge_vec!("nvml_supported_memory_clock", "supported memory clock speed", &["device", "uuid", "clock"])?,
+            gv_supported_graphics_clock: register_gauge_vec!("nvml_supported_graphics_clock", "supported graphics clock speed for a memory clock speed", &["device", "uuid", "mem_clock", "clock"])?,
+            gv_memory_info: register_gauge_vec!("nvml_memory_info", "memory information", &["device", "uuid", "pool", "state"])?,
             gv_display_active: register_gauge_vec!("nvml_display_active", "display active", dl)?,
             gv_display_mode: register_gauge_vec!("nvml_display_mode", "display mode", dl)?,
-            gv_encoder_capacity_h264: register_gauge_vec!("nvml_encoder_capacity_h264", "encoder capacity", dl)?,
-            gv_encoder_capacity_hevc: register_gauge_vec!("nvml_encoder_capacity_hevc", "encoder capacity", dl)?,
+            gv_encoder_capacity: register_gauge_vec!("nvml_encoder_capacity", "encoder capacity", &["device", "uuid", "codec"])?,
+            gv_encoder_capacity_h264: register_gauge_vec!("nvml_encoder_capacity_h264", "encoder capacity (deprecated, use nvml_encoder_capacity)", dl)?,
+            gv_encoder_capacity_hevc: register_gauge_vec!("nvml_encoder_capacity_hevc", "encoder capacity (deprecated, use nvml_encoder_capacity)", dl)?,
+            gv_encoder_utilization: register_gauge_vec!("nvml_encoder_utilization", "encoder utilization", dl)?,
+            gv_encoder_sampling_period: register_gauge_vec!("nvml_encoder_sampling_period", "encoder utilization sampling period in microseconds", dl)?,
+            gv_decoder_utilization: register_gauge_vec!("nvml_decoder_utilization", "decoder utilization", dl)?,
+            gv_decoder_sampling_period: register_gauge_vec!("nvml_decoder_sampling_period", "decoder utilization sampling period in microseconds", dl)?,
             gv_encoder_stats_sessions_count: register_gauge_vec!("nvml_encoder_stats_sessions_count", "session count for encoder sessions", dl)?,
             gv_encoder_stats_average_fps: register_gauge_vec!("nvml_encoder_stats_average_fps", "average fps for encoder sessions", dl)?,
             gv_encoder_stats_average_latency: register_gauge_vec!("nvml_encoder_stats_average_latency", "average latency for encoder sessions", dl)?,
             gv_current_clocks_throttle_reasons: register_gauge_vec!("nvml_current_clocks_throttle_reasons", "current clock throttling reason code", &["device", "uuid", "reason"])?,
+            cv_throttle_violation_seconds: register_counter_vec!("nvml_throttle_violation_seconds_total", "time during which clocks were limited by a performance policy", &["device", "uuid", "reason"])?,
+            cv_energy: register_counter_vec!("nvml_energy_joules_total", "energy consumed since the driver was loaded", dl)?,
+            cv_memory_errors: register_counter_vec!("nvml_memory_errors_total", "memory errors counted by the driver", &["device", "uuid", "mem_error", "ecc_counter", "mem_location"])?,
             gv_memory_error_counters: register_gauge_vec!("nvml_memory_error_counters", "memory error counters", &["device", "uuid", "mem_error", "ecc_counter", "mem_location"])?,
+            gv_ecc_mode: register_gauge_vec!("nvml_ecc_mode", "ECC mode enabled", &["device", "uuid", "state"])?,
+            gv_compute_mode: register_gauge_vec!("nvml_compute_mode", "compute mode", &["device", "uuid", "mode"])?,
+            #[cfg(target_os = "linux")]
+            gv_persistence_mode: register_gauge_vec!("nvml_persistence_mode", "persistence mode enabled", dl)?,
+            gv_auto_boost: register_gauge_vec!("nvml_auto_boost", "auto boosted clocks enabled", &["device", "uuid", "state"])?,
+            gv_gpu_operation_mode: register_gauge_vec!("nvml_gpu_operation_mode", "GPU operation mode", &["device", "uuid", "state", "mode"])?,
+            #[cfg(target_os = "windows")]
+            gv_driver_model: register_gauge_vec!("nvml_driver_model", "driver model", &["device", "uuid", "state", "mode"])?,
+            gv_retired_pages: register_gauge_vec!("nvml_retired_pages", "number of retired memory pages", &["device", "uuid", "cause"])?,
+            gv_retired_pages_pending: register_gauge_vec!("nvml_retired_pages_pending", "memory pages are pending retirement until the next reset", dl)?,
+            gv_remapped_rows: register_gauge_vec!("nvml_remapped_rows", "number of remapped memory rows", &["device", "uuid", "type"])?,
+            gv_remapped_rows_pending: register_gauge_vec!("nvml_remapped_rows_pending", "memory rows are pending remapping until the next reset", dl)?,
+            gv_remapped_rows_failure: register_gauge_vec!("nvml_remapped_rows_failure", "a memory row remapping failed", dl)?,
+            gv_row_remapper_histogram: register_gauge_vec!("nvml_row_remapper_histogram", "number of memory banks by remaining row remapping availability", &["device", "uuid", "availability"])?,
+            #[cfg(target_os = "linux")]
+            cv_xid_errors: register_counter_vec!("nvml_xid_errors_total", "number of critical XID errors", &["device", "uuid", "xid"])?,
+            #[cfg(target_os = "linux")]
+            cv_xid_events: register_counter_vec!("nvml_xid_events_total", "number of critical XID errors of any XID", dl)?,
+            #[cfg(target_os = "linux")]
+            gv_last_xid: register_gauge_vec!("nvml_last_xid", "last critical XID error", dl)?,
+            #[cfg(target_os = "linux")]
+            gv_last_xid_timestamp: register_gauge_vec!("nvml_last_xid_timestamp_seconds", "time of the last critical XID error", dl)?,
+            #[cfg(target_os = "linux")]
+            cv_ecc_error_events: register_counter_vec!("nvml_ecc_error_events_total", "number of ECC error events", &["device", "uuid", "type"])?,
+            #[cfg(target_os = "linux")]
+            cv_clock_change_events: register_counter_vec!("nvml_clock_change_events_total", "number of clock change events", dl)?,
+            gv_device_job_info: register_gauge_vec!("nvml_device_job_info", "slurm jobs running processes on the device", jl)?,
+            gv_job_memory_used: register_gauge_vec!("nvml_job_memory_used", "GPU memory used by the processes of a slurm job", jl)?,
+            gv_accounting_enabled: register_gauge_vec!("nvml_accounting_enabled", "accounting mode enabled", dl)?,
+            cv_accounting_processes: register_counter_vec!("nvml_accounting_processes_total", "number of completed processes seen in accounting stats", dl)?,
+            cv_accounting_runtime_seconds: register_counter_vec!("nvml_accounting_runtime_seconds_total", "time during which the compute context of completed processes was active", dl)?,
+            cv_accounting_gpu_seconds: register_counter_vec!("nvml_accounting_gpu_seconds_total", "runtime of completed processes weighted by their lifetime GPU utilization", dl)?,
+            cv_accounting_max_memory_bytes: register_counter_vec!("nvml_accounting_max_memory_bytes_total", "sum of the max memory allocated by completed processes", dl)?,
+            gv_process_utilization_sm: register_gauge_vec!("nvml_process_utilization_sm", "SM utilization of a process", pl)?,
+            gv_process_utilization_memory: register_gauge_vec!("nvml_process_utilization_memory", "frame buffer memory utilization of a process", pl)?,
+            gv_process_utilization_encoder: register_gauge_vec!("nvml_process_utilization_encoder", "encoder utilization of a process", pl)?,
+            gv_process_utilization_decoder: register_gauge_vec!("nvml_process_utilization_decoder", "decoder utilization of a process", pl)?,
+            gv_encoder_session_fps: register_gauge_vec!("nvml_encoder_session_fps", "average fps of an encoder session", esl)?,
+            gv_encoder_session_latency: register_gauge_vec!("nvml_encoder_session_latency", "average latency of an encoder session in microseconds", esl)?,
+            gv_encoder_session_hres: register_gauge_vec!("nvml_encoder_session_hres", "horizontal resolution of an encoder session", esl)?,
+            gv_encoder_session_vres: register_gauge_vec!("nvml_encoder_session_vres", "vertical resolution of an encoder session", esl)?,
+            gv_fbc_session_fps: register_gauge_vec!("nvml_fbc_session_fps", "average fps of a frame buffer capture session", fsl)?,
+            gv_fbc_session_latency: register_gauge_vec!("nvml_fbc_session_latency", "average latency of a frame buffer capture session in microseconds", fsl)?,
+            gv_fbc_session_hres: register_gauge_vec!("nvml_fbc_session_hres", "horizontal resolution of a frame buffer capture session", fsl)?,
+            gv_fbc_session_vres: register_gauge_vec!("nvml_fbc_session_vres", "vertical resolution of a frame buffer capture session", fsl)?,
+            gv_sessions_omitted: register_gauge_vec!("nvml_sessions_omitted", "sessions not exported because of --max-sessions", &["device", "uuid", "kind"])?,
+            #[cfg(target_os = "linux")]
+            gv_numa_node: register_gauge_vec!("nvml_numa_node", "NUMA node closest to the device", dl)?,
+            #[cfg(target_os = "linux")]
+            gv_cpu_affinity: register_gauge_vec!("nvml_cpu_affinity_info", "CPUs closest to the device", &["device", "uuid", "cpus"])?,
+            #[cfg(target_os = "linux")]
+            gv_topology_link: register_gauge_vec!("nvml_topology_link", "closest common ancestor of two devices", &["device", "uuid", "peer", "level"])?,
+            #[cfg(target_os = "linux")]
+            gv_p2p_status: register_gauge_vec!("nvml_p2p_status", "P2P capability status between two devices", &["device", "uuid", "peer", "capability", "status"])?,
+            gv_gpm_sm_activity: register_gauge_vec!("nvml_gpm_sm_activity", "percentage of time SMs were busy", dl)?,
+            gv_gpm_sm_occupancy: register_gauge_vec!("nvml_gpm_sm_occupancy", "percentage of warps resident on SMs relative to the maximum", dl)?,
+            gv_gpm_tensor_activity: register_gauge_vec!("nvml_gpm_tensor_activity", "percentage of time tensor cores were busy", dl)?,
+            gv_gpm_dram_bandwidth_utilization: register_gauge_vec!("nvml_gpm_dram_bandwidth_utilization", "percentage of DRAM bandwidth used", dl)?,
+            gv_gpm_pipe_activity: register_gauge_vec!("nvml_gpm_pipe_activity", "percentage of time floating point pipes were busy", &["device", "uuid", "pipe"])?,
+            gv_gpm_pcie_bandwidth: register_gauge_vec!("nvml_gpm_pcie_bytes_per_second", "PCIe bandwidth", &["device", "uuid", "direction"])?,
+            gv_gpm_nvlink_bandwidth: register_gauge_vec!("nvml_gpm_nvlink_bytes_per_second", "NVLink bandwidth over all links", &["device", "uuid", "direction"])?,
+            gv_mig_gpm_sm_activity: register_gauge_vec!("nvml_mig_gpm_sm_activity", "percentage of time SMs of a GPU instance were busy", gil)?,
+            gv_mig_gpm_sm_occupancy: register_gauge_vec!("nvml_mig_gpm_sm_occupancy", "percentage of warps resident on SMs of a GPU instance relative to the maximum", gil)?,
+            gv_mig_gpm_tensor_activity: register_gauge_vec!("nvml_mig_gpm_tensor_activity", "percentage of time tensor cores of a GPU instance were busy", gil)?,
+            gv_mig_gpm_dram_bandwidth_utilization: register_gauge_vec!("nvml_mig_gpm_dram_bandwidth_utilization", "percentage of DRAM bandwidth used by a GPU instance", gil)?,
+            gv_unit_info: register_gauge_vec!("nvml_unit_info", "S-class unit information", &["unit", "id", "name", "serial", "firmware_version"])?,
+            gv_unit_temperature: register_gauge_vec!("nvml_unit_temperature", "unit temperature", &["unit", "sensor"])?,
+            gv_unit_psu_voltage: register_gauge_vec!("nvml_unit_psu_voltage", "unit PSU voltage in volts", &["unit"])?,
+            gv_unit_psu_current: register_gauge_vec!("nvml_unit_psu_current", "unit PSU current in amperes", &["unit"])?,
+            gv_unit_psu_power_draw: register_gauge_vec!("nvml_unit_psu_power_draw", "unit PSU power draw in watts", &["unit"])?,
+            gv_unit_psu_state: register_gauge_vec!("nvml_unit_psu_state", "unit PSU state", &["unit", "state"])?,
+            gv_unit_fan_speed: register_gauge_vec!("nvml_unit_fan_speed", "unit fan speed in RPM", &["unit", "fan"])?,
+            gv_unit_fan_failed: register_gauge_vec!("nvml_unit_fan_failed", "unit fan failed", &["unit", "fan"])?,
+            gv_unit_led_state: register_gauge_vec!("nvml_unit_led_state", "unit LED color and the cause of an amber LED", &["unit", "color", "cause"])?,
+            gv_mig_mode: register_gauge_vec!("nvml_mig_mode", "MIG mode enabled", &["device", "uuid", "state"])?,
+            gv_mig_device_info: register_gauge_vec!("nvml_mig_device_info", "MIG device identity", ml)?,
+            gv_mig_memory_info: register_gauge_vec!("nvml_mig_memory_info", "MIG device memory information", mml)?,
+            gv_mig_multiprocessor_count: register_gauge_vec!("nvml_mig_multiprocessor_count", "number of SMs of a MIG device", ml)?,
+            gv_mig_gpu_instance_slice_count: register_gauge_vec!("nvml_mig_gpu_instance_slice_count", "number of GPU instance slices of a MIG device", ml)?,
+            gv_mig_compute_instance_slice_count: register_gauge_vec!("nvml_mig_compute_instance_slice_count", "number of compute instance slices of a MIG device", ml)?,
+            gv_mig_running_compute_processes_count: register_gauge_vec!("nvml_mig_running_compute_processes_count", "number of running compute processes on a MIG device", ml)?,
+            gv_vgpu_supported_types: register_gauge_vec!("nvml_vgpu_supported_types", "number of vGPU types supported by the device", dl)?,
+            gv_vgpu_creatable_types: register_gauge_vec!("nvml_vgpu_creatable_types", "number of vGPU types that can currently be created on the device", dl)?,
+            gv_vgpu_active_count: register_gauge_vec!("nvml_vgpu_active_count", "number of active vGPU instances", dl)?,
+            gv_vgpu_info: register_gauge_vec!("nvml_vgpu_info", "vGPU instance type and VM", &["device", "uuid", "vgpu_uuid", "vgpu_type", "vm_id"])?,
+            gv_vgpu_fb_usage: register_gauge_vec!("nvml_vgpu_fb_usage", "framebuffer usage of a vGPU instance in bytes", vl)?,
+            gv_vgpu_encoder_stats_sessions_count: register_gauge_vec!("nvml_vgpu_encoder_stats_sessions_count", "session count for encoder sessions of a vGPU instance", vl)?,
+            gv_vgpu_encoder_stats_average_fps: register_gauge_vec!("nvml_vgpu_encoder_stats_average_fps", "average fps for encoder sessions of a vGPU instance", vl)?,
+            gv_vgpu_encoder_stats_average_latency: register_gauge_vec!("nvml_vgpu_encoder_stats_average_latency", "average latency for encoder sessions of a vGPU instance", vl)?,
+            gv_vgpu_fbc_stats_sessions_count: register_gauge_vec!("nvml_vgpu_fbc_stats_sessions_count", "session count for frame buffer capture sessions of a vGPU instance", vl)?,
+            gv_vgpu_fbc_stats_average_fps: register_gauge_vec!("nvml_vgpu_fbc_stats_average_fps", "average fps for frame buffer capture sessions of a vGPU instance", vl)?,
+            gv_vgpu_fbc_stats_average_latency: register_gauge_vec!("nvml_vgpu_fbc_stats_average_latency", "average latency for frame buffer capture sessions of a vGPU instance", vl)?,
+            gv_vgpu_licensed: register_gauge_vec!("nvml_vgpu_licensed", "vGPU instance is licensed", vl)?,
         })
     }
 }
 
+/// Metrics registered once for all tests, since the default registry rejects duplicate registrations.
+#[cfg(test)]
+fn test_metrics() -> &'static Metrics {
+    static METRICS: std::sync::OnceLock<Metrics> = std::sync::OnceLock::new();
+    METRICS.get_or_init(|| Metrics::new().unwrap())
+}
+
+/// Performance policies whose violation time is exported.
+const PERFORMANCE_POLICIES: [PerformancePolicy; 8] = [
+    PerformancePolicy::Power,
+    PerformancePolicy::Thermal,
+    PerformancePolicy::SyncBoost,
+    PerformancePolicy::BoardLimit,
+    PerformancePolicy::LowUtilization,
+    PerformancePolicy::Reliability,
+    PerformancePolicy::TotalAppClocks,
+    PerformancePolicy::TotalBaseClocks,
+];
+
+/// Throttle reasons exported individually, including `NONE` which is always reported as active.
+const THROTTLE_REASONS: [ThrottleReasons; 10] = [
+    ThrottleReasons::GPU_IDLE,
+    ThrottleReasons::APPLICATIONS_CLOCKS_SETTING,
+    ThrottleReasons::SW_POWER_CAP,
+    ThrottleReasons::HW_SLOWDOWN,
+    ThrottleReasons::SYNC_BOOST,
+    ThrottleReasons::SW_THERMAL_SLOWDOWN,
+    ThrottleReasons::HW_THERMAL_SLOWDOWN,
+    ThrottleReasons::HW_POWER_BRAKE_SLOWDOWN,
+    ThrottleReasons::DISPLAY_CLOCK_SETTING,
+    ThrottleReasons::NONE,
+];
+
+const MEMORY_LOCATIONS: [MemoryLocation; 8] = [
+    MemoryLocation::Cbu,
+    MemoryLocation::Device,
+    MemoryLocation::L1Cache,
+    MemoryLocation::L2Cache,
+    MemoryLocation::RegisterFile,
+    MemoryLocation::Shared,
+    MemoryLocation::SRAM,
+    MemoryLocation::Texture,
+];
+
 fn gather(ctx: Arc<Context>) -> Result<(), NvmlError> {
     let now = SystemTime::now();
-    debug!("starting NVML gather at {}", chrono::Utc::now().format("%c").to_string());
+    debug!("starting NVML gather at {}", chrono::Utc::now().format("%c"));
 
     let exporter_version = std::env!("CARGO_PKG_VERSION");
     ctx.metrics.gv_exporter.with_label_values(&[exporter_version]).set(1.);
@@ -213,6 +705,54 @@ fn gather(ctx: Arc<Context>) -> Result<(), NvmlError> {
     let count = ctx.nvml.device_count()?;
     ctx.metrics.g_device_count.set(count as f64);
 
+    if ctx.opts.enable_slurm_jobs {
+        // jobs come and go between scrapes, so only keep the series seen in this gather
+        ctx.metrics.gv_device_job_info.reset();
+        ctx.metrics.gv_job_memory_used.reset();
+    }
+    if ctx.opts.enable_process_utilization {
+        ctx.metrics.gv_process_utilization_sm.reset();
+        ctx.metrics.gv_process_utilization_memory.reset();
+        ctx.metrics.gv_process_utilization_encoder.reset();
+        ctx.metrics.gv_process_utilization_decoder.reset();
+    }
+    // unit states are exported as labels
+    ctx.metrics.gv_unit_psu_state.reset();
+    ctx.metrics.gv_unit_led_state.reset();
+    timed!("units", units::collect(&ctx.metrics, &ctx.nvml));
+
+    // MIG devices can be reconfigured at runtime
+    ctx.metrics.gv_mig_device_info.reset();
+    ctx.metrics.gv_mig_memory_info.reset();
+    ctx.metrics.gv_mig_multiprocessor_count.reset();
+    ctx.metrics.gv_mig_gpu_instance_slice_count.reset();
+    ctx.metrics.gv_mig_compute_instance_slice_count.reset();
+    ctx.metrics.gv_mig_running_compute_processes_count.reset();
+    ctx.metrics.gv_mig_gpm_sm_activity.reset();
+    ctx.metrics.gv_mig_gpm_sm_occupancy.reset();
+    ctx.metrics.gv_mig_gpm_tensor_activity.reset();
+    ctx.metrics.gv_mig_gpm_dram_bandwidth_utilization.reset();
+    // as are vGPU instances, whenever VMs start and stop
+    ctx.metrics.gv_vgpu_info.reset();
+    ctx.metrics.gv_vgpu_fb_usage.reset();
+    ctx.metrics.gv_vgpu_encoder_stats_sessions_count.reset();
+    ctx.metrics.gv_vgpu_encoder_stats_average_fps.reset();
+    ctx.metrics.gv_vgpu_encoder_stats_average_latency.reset();
+    ctx.metrics.gv_vgpu_fbc_stats_sessions_count.reset();
+    ctx.metrics.gv_vgpu_fbc_stats_average_fps.reset();
+    ctx.metrics.gv_vgpu_fbc_stats_average_latency.reset();
+    ctx.metrics.gv_vgpu_licensed.reset();
+    if ctx.opts.enable_sessions {
+        ctx.metrics.gv_encoder_session_fps.reset();
+        ctx.metrics.gv_encoder_session_latency.reset();
+        ctx.metrics.gv_encoder_session_hres.reset();
+        ctx.metrics.gv_encoder_session_vres.reset();
+        ctx.metrics.gv_fbc_session_fps.reset();
+        ctx.metrics.gv_fbc_session_latency.reset();
+        ctx.metrics.gv_fbc_session_hres.reset();
+        ctx.metrics.gv_fbc_session_vres.reset();
+    }
+
     for device_index in 0..count {
         let device = ctx.nvml.device_by_index(device_index)?;
         let dev_idx_string = device_index.to_string();
@@ -221,30 +761,6 @@ fn gather(ctx: Arc<Context>) -> Result<(), NvmlError> {
         let dev_uuid = dev_uuid_string.as_str();
 
         let dl = &[dev_idx_str, dev_uuid];
-        macro_rules! set_gv {
-            ( $member:expr, $labels:expr, $val:expr ) => {
-                $member.with_label_values($labels).set($val as f64);
-                if cfg!(debug_assertions) {
-                    trace!(stringify!($member));
-                }
-            };
-        }
-
-        #[cfg(debug_assertions)]
-        macro_rules! timed {
-            ( $n:expr, $e:expr ) => {
-                let now = SystemTime::now();
-                let _ = $e;
-                trace!("{}: took {}ms", $n, now.elapsed().unwrap().as_millis());
-            };
-        }
-        #[cfg(not(debug_assertions))]
-        macro_rules! timed {
-            ( $n:expr, $e:expr ) => {
-                $e
-            };
-        }
-
         timed!("core", {
             set_gv!(ctx.metrics.gv_device_temp, dl, device.temperature(TemperatureSensor::Gpu)? as f64);
             set_gv!(ctx.metrics.gv_device_power_usage, dl, (device.power_usage()? as f64) / 1000.);
@@ -265,10 +781,56 @@ fn gather(ctx: Arc<Context>) -> Result<(), NvmlError> {
                 Err(e) => warn!("error collecting utilization rates: {:?}", e),
             }
 
+            match device.total_energy_consumption() {
+                Ok(energy) => ctx.cumulative.observe(&ctx.metrics.cv_energy, dl, energy as f64 / 1000.),
+                Err(NvmlError::NotSupported) => (),
+                Err(e) => warn!("error collecting total energy consumption: {:?}", e),
+            }
+
+            for (codec, capacity) in [
+                ("h264", device.encoder_capacity(EncoderType::H264)),
+                ("hevc", device.encoder_capacity(EncoderType::HEVC)),
+                ("av1", ctx.raw.encoder_capacity_av1(&device)),
+            ] {
+                match capacity {
+                    Ok(capacity) => {
+                        set_gv!(ctx.metrics.gv_encoder_capacity, &[dev_idx_str, dev_uuid, codec], capacity);
+                        match codec {
+                            "h264" => {
+                                set_gv!(ctx.metrics.gv_encoder_capacity_h264, dl, capacity);
+                            }
+                            "hevc" => {
+                                set_gv!(ctx.metrics.gv_encoder_capacity_hevc, dl, capacity);
+                            }
+                            _ => (),
+                        }
+                    }
+                    Err(e) => {
+                        if cfg!(debug_assertions) {
+                            trace!("failed to collect {} encoder capacity: {:?}", codec, e);
+                        }
+                    }
+                }
+            }
+
+            match device.encoder_utilization() {
+                Ok(util) => {
+                    set_gv!(ctx.metrics.gv_encoder_utilization, dl, util.utilization);
+                    set_gv!(ctx.metrics.gv_encoder_sampling_period, dl, util.sampling_period);
+                }
+                Err(e) => warn!("error collecting encoder utilization: {:?}", e),
+            }
+
+            match device.decoder_utilization() {
+                Ok(util) => {
+                    set_gv!(ctx.metrics.gv_decoder_utilization, dl, util.utilization);
+                    set_gv!(ctx.metrics.gv_decoder_sampling_period, dl, util.sampling_period);
+                }
+                Err(e) => warn!("error collecting decoder utilization: {:?}", e),
+            }
+
             match device.encoder_stats() {
                 Ok(encoder_stats) => {
-                    set_gv!(ctx.metrics.gv_encoder_capacity_h264, dl, device.encoder_capacity(EncoderType::H264)?);
-                    set_gv!(ctx.metrics.gv_encoder_capacity_hevc, dl, device.encoder_capacity(EncoderType::HEVC)?);
                     set_gv!(ctx.metrics.gv_encoder_stats_sessions_count, dl, encoder_stats.session_count as f64);
                     set_gv!(ctx.metrics.gv_encoder_stats_average_fps, dl, encoder_stats.average_fps as f64);
                     set_gv!(ctx.metrics.gv_encoder_stats_average_latency, dl, encoder_stats.average_latency as f64);
@@ -285,58 +847,87 @@ fn gather(ctx: Arc<Context>) -> Result<(), NvmlError> {
                 Err(e) => warn!("error collecting framebuffer capture stats: {:?}", e),
             }
 
-            match device.memory_info() {
+            match ctx.raw.memory_info_v2(&device) {
                 Ok(mem) => {
-                    ctx.metrics.gv_memory_info.with_label_values(&[dev_idx_str, dev_uuid, "free"]).set(mem.free as f64);
-                    ctx.metrics.gv_memory_info.with_label_values(&[dev_idx_str, dev_uuid, "total"]).set(mem.total as f64);
-                    ctx.metrics.gv_memory_info.with_label_values(&[dev_idx_str, dev_uuid, "used"]).set(mem.used as f64);
+                    ctx.metrics.gv_memory_info.with_label_values(&[dev_idx_str, dev_uuid, "framebuffer", "free"]).set(mem.free as f64);
+                    ctx.metrics.gv_memory_info.with_label_values(&[dev_idx_str, dev_uuid, "framebuffer", "total"]).set(mem.total as f64);
+                    // v2 excludes reserved memory from used, v1 includes it; keep the v1 meaning whatever the driver
+                    ctx.metrics.gv_memory_info.with_label_values(&[dev_idx_str, dev_uuid, "framebuffer", "used"]).set((mem.used + mem.reserved) as f64);
+                    ctx.metrics.gv_memory_info.with_label_values(&[dev_idx_str, dev_uuid, "framebuffer", "reserved"]).set(mem.reserved as f64);
+                }
+                Err(e) => match device.memory_info() {
+                    Ok(mem) => {
+                        debug!("memory info v2 is not available, falling back to v1 without reserved memory: {:?}", e);
+                        ctx.metrics.gv_memory_info.with_label_values(&[dev_idx_str, dev_uuid, "framebuffer", "free"]).set(mem.free as f64);
+                        ctx.metrics.gv_memory_info.with_label_values(&[dev_idx_str, dev_uuid, "framebuffer", "total"]).set(mem.total as f64);
+                        ctx.metrics.gv_memory_info.with_label_values(&[dev_idx_str, dev_uuid, "framebuffer", "used"]).set(mem.used as f64);
+                    }
+                    Err(e) => warn!("error fetching current memory info: {:?}", e),
+                },
+            };
+
+            match device.bar1_memory_info() {
+                Ok(bar1) => {
+                    ctx.metrics.gv_memory_info.with_label_values(&[dev_idx_str, dev_uuid, "bar1", "free"]).set(bar1.free as f64);
+                    ctx.metrics.gv_memory_info.with_label_values(&[dev_idx_str, dev_uuid, "bar1", "total"]).set(bar1.total as f64);
+                    ctx.metrics.gv_memory_info.with_label_values(&[dev_idx_str, dev_uuid, "bar1", "used"]).set(bar1.used as f64);
                 }
-                Err(e) => warn!("error fetching current memory info: {:?}", e),
+                Err(e) => warn!("error fetching BAR1 memory info: {:?}", e),
             };
         });
 
-        timed!("clocks", {
-            /*
-             * Only the "current" clock series seems to pull on @oko's RTX 3000 series card
-             */
-            for cid in &[ClockId::Current, ClockId::TargetAppClock, ClockId::DefaultAppClock, ClockId::CustomerMaxBoost] {
-                let cid_str = clock_id_str(cid.clone());
-                for ctype in &[Clock::Graphics, Clock::Memory, Clock::SM, Clock::Video] {
-                    let ctype_str = clock_type_str(ctype.clone());
-                    let clock = device.clock(ctype.clone(), cid.clone());
-                    if clock.is_ok() {
-                        if cfg!(debug_assertions) {
-                            trace!("got metrics for clock ID {:?} and type {:?}", cid, ctype);
-                        }
-                        set_gv!(ctx.metrics.gv_clock, &[dev_idx_str, dev_uuid, cid_str, ctype_str], clock.unwrap() as f64);
+        timed!("clocks", clocks::collect(&ctx.metrics, &device, dl));
+
+        if ctx.opts.enable_supported_clocks && !ctx.supported_clocks_collected.lock().unwrap().contains(dev_uuid) {
+            // supported clocks do not change, so they are only collected until the first success
+            timed!("supported_clocks", {
+                let collected = match clocks::collect_supported(&ctx.metrics, &device, dl) {
+                    Ok(()) => true,
+                    Err(NvmlError::NotSupported) => {
+                        debug!("supported clocks are not reported by device {}", dev_idx_str);
+                        true
                     }
-                    let aclock = device.clock(ctype.clone(), cid.clone());
-                    if aclock.is_ok() {
-                        if cfg!(debug_assertions) {
-                            trace!("got metrics for applications clock ID {:?} and type {:?}", cid, ctype);
-                        }
-                        set_gv!(ctx.metrics.gv_applications_clock, &[dev_idx_str, dev_uuid, cid_str, ctype_str], aclock.unwrap() as f64);
+                    Err(e) => {
+                        warn!("error collecting supported clocks, retrying on the next gather: {:?}", e);
+                        false
                     }
+                };
+                if collected {
+                    ctx.supported_clocks_collected.lock().unwrap().insert(dev_uuid.to_string());
                 }
-            }
-        });
+            });
+        }
+
+        timed!("gpm", gpm::collect(&ctx.metrics, &ctx.raw, &ctx.gpm_samples, &device, dl));
+        timed!("field_values", ctx.field_values.collect(&ctx.raw, &ctx.cumulative, &device, dl));
+
+        #[cfg(target_os = "linux")]
+        timed!("topology", topology::collect(&ctx.metrics, &ctx.nvml, &ctx.raw, &ctx.topology, &device, dl));
+
+        if ctx.opts.enable_slurm_jobs {
+            timed!("slurm_jobs", slurm::collect(&ctx.metrics, &ctx.slurm_user_names, &device, dl));
+        }
+
+        timed!("mig", mig::collect(&ctx.metrics, &ctx.raw, &ctx.gpm_samples, &device, dl));
+        timed!("vgpu", vgpu::collect(&ctx.metrics, &ctx.raw, &device, dl));
+
+        if ctx.opts.enable_sessions {
+            timed!("sessions", sessions::collect(&ctx.metrics, &ctx.raw, &device, dl, ctx.opts.max_sessions));
+        }
+
+        if ctx.opts.enable_process_utilization {
+            timed!("process_utilization", process_utilization::collect(&ctx.metrics, &ctx.process_utilization_last_seen, &device, dl));
+        }
+
+        if ctx.opts.enable_accounting {
+            timed!("accounting", accounting::collect(&ctx.metrics, &ctx.accounting_seen, &device, dl));
+        }
 
         if ctx.opts.enable_throttle_reasons {
             timed!("throttle_reasons", {
                 match device.current_throttle_reasons() {
                     Ok(throttle_reasons) => {
-                        for reason in [
-                            ThrottleReasons::GPU_IDLE,
-                            ThrottleReasons::APPLICATIONS_CLOCKS_SETTING,
-                            ThrottleReasons::SW_POWER_CAP,
-                            ThrottleReasons::HW_SLOWDOWN,
-                            ThrottleReasons::SYNC_BOOST,
-                            ThrottleReasons::SW_THERMAL_SLOWDOWN,
-                            ThrottleReasons::HW_THERMAL_SLOWDOWN,
-                            ThrottleReasons::HW_POWER_BRAKE_SLOWDOWN,
-                            ThrottleReasons::DISPLAY_CLOCK_SETTING,
-                            ThrottleReasons::NONE,
-                        ] {
+                        for reason in THROTTLE_REASONS {
                             ctx.metrics
                                 .gv_current_clocks_throttle_reasons
                                 .with_label_values(&[dev_idx_str, dev_uuid, throttle_reason_str(reason)])
@@ -350,30 +941,101 @@ fn gather(ctx: Arc<Context>) -> Result<(), NvmlError> {
             warn!("skipping throttle reasons collection");
         }
 
+        timed!("violation_status", {
+            for policy in PERFORMANCE_POLICIES {
+                match device.violation_status(policy.clone()) {
+                    Ok(violation) => {
+                        let labels = &[dev_idx_str, dev_uuid, performance_policy_str(&policy)];
+                        ctx.cumulative.observe(&ctx.metrics.cv_throttle_violation_seconds, labels, violation.violation_time as f64 / 1e9);
+                    }
+                    Err(e) => {
+                        if cfg!(debug_assertions) {
+                            trace!("failed to collect violation status for {}: {:?}", performance_policy_str(&policy), e);
+                        }
+                    }
+                }
+            }
+        });
+
+        timed!("modes", {
+            match device.compute_mode() {
+                Ok(current) => {
+                    for mode in [ComputeMode::Default, ComputeMode::ExclusiveThread, ComputeMode::Prohibited, ComputeMode::ExclusiveProcess] {
+                        set_gv!(ctx.metrics.gv_compute_mode, &[dev_idx_str, dev_uuid, compute_mode_str(&mode)], if mode == current { 1 } else { 0 });
+                    }
+                }
+                Err(e) => warn!("error fetching compute mode: {:?}", e),
+            }
+            #[cfg(target_os = "linux")]
+            match device.is_in_persistent_mode() {
+                Ok(enabled) => {
+                    set_gv!(ctx.metrics.gv_persistence_mode, dl, if enabled { 1 } else { 0 });
+                }
+                Err(e) => {
+                    if cfg!(debug_assertions) {
+                        trace!("failed to collect persistence mode: {:?}", e);
+                    }
+                }
+            }
+            match device.is_accounting_enabled() {
+                Ok(enabled) => {
+                    set_gv!(ctx.metrics.gv_accounting_enabled, dl, if enabled { 1 } else { 0 });
+                }
+                Err(e) => {
+                    if cfg!(debug_assertions) {
+                        trace!("failed to collect accounting mode: {:?}", e);
+                    }
+                }
+            }
+            match device.auto_boosted_clocks_enabled() {
+                Ok(auto_boost) => {
+                    set_gv!(ctx.metrics.gv_auto_boost, &[dev_idx_str, dev_uuid, "current"], if auto_boost.is_enabled { 1 } else { 0 });
+                    set_gv!(ctx.metrics.gv_auto_boost, &[dev_idx_str, dev_uuid, "default"], if auto_boost.is_enabled_default { 1 } else { 0 });
+                }
+                Err(e) => {
+                    if cfg!(debug_assertions) {
+                        trace!("failed to collect auto boost state: {:?}", e);
+                    }
+                }
+            }
+            match device.gpu_operation_mode() {
+                Ok(state) => {
+                    for mode in [OperationMode::AllOn, OperationMode::Compute, OperationMode::LowDP] {
+                        set_gv!(ctx.metrics.gv_gpu_operation_mode, &[dev_idx_str, dev_uuid, "current", operation_mode_str(&mode)], if mode == state.current { 1 } else { 0 });
+                        set_gv!(ctx.metrics.gv_gpu_operation_mode, &[dev_idx_str, dev_uuid, "pending", operation_mode_str(&mode)], if mode == state.pending { 1 } else { 0 });
+                    }
+                }
+                Err(e) => {
+                    if cfg!(debug_assertions) {
+                        trace!("failed to collect GPU operation mode: {:?}", e);
+                    }
+                }
+            }
+            #[cfg(target_os = "windows")]
+            if let Ok(state) = device.driver_model() {
+                for mode in [DriverModel::WDDM, DriverModel::WDM] {
+                    set_gv!(ctx.metrics.gv_driver_model, &[dev_idx_str, dev_uuid, "current", driver_model_str(&mode)], if mode == state.current { 1 } else { 0 });
+                    set_gv!(ctx.metrics.gv_driver_model, &[dev_idx_str, dev_uuid, "pending", driver_model_str(&mode)], if mode == state.pending { 1 } else { 0 });
+                }
+            }
+        });
+
         match device.is_ecc_enabled() {
             Ok(ecc_state) => {
+                set_gv!(ctx.metrics.gv_ecc_mode, &[dev_idx_str, dev_uuid, "current"], if ecc_state.currently_enabled { 1 } else { 0 });
+                set_gv!(ctx.metrics.gv_ecc_mode, &[dev_idx_str, dev_uuid, "pending"], if ecc_state.pending_enabled { 1 } else { 0 });
                 timed!("memory_errors", {
                     if ecc_state.currently_enabled {
                         debug!("ECC enabled, collecting memory error statistics");
-                        for loc in [
-                            MemoryLocation::Cbu,
-                            MemoryLocation::Device,
-                            MemoryLocation::L1Cache,
-                            MemoryLocation::L2Cache,
-                            MemoryLocation::RegisterFile,
-                            MemoryLocation::Shared,
-                            MemoryLocation::SRAM,
-                            MemoryLocation::Texture,
-                        ] {
+                        for loc in MEMORY_LOCATIONS {
                             for ecc in [EccCounter::Aggregate, EccCounter::Volatile] {
                                 for err in [MemoryError::Corrected, MemoryError::Uncorrected] {
                                     match device.memory_error_counter(err.clone(), ecc.clone(), loc.clone()) {
-                                        Ok(ct) => ctx
-                                            .metrics
-                                            .gv_memory_error_counters
-                                            // &["device", "mem_error", "ecc_counter", "mem_location"]
-                                            .with_label_values(&[dev_idx_str, dev_uuid, memory_error_type_str(&err), ecc_counter_type_str(&ecc), memory_location_str(&loc)])
-                                            .set(ct as f64),
+                                        Ok(ct) => {
+                                            let labels = &[dev_idx_str, dev_uuid, memory_error_type_str(&err), ecc_counter_type_str(&ecc), memory_location_str(&loc)];
+                                            set_gv!(ctx.metrics.gv_memory_error_counters, labels, ct as f64);
+                                            ctx.cumulative.observe(&ctx.metrics.cv_memory_errors, labels, ct as f64);
+                                        }
                                         Err(e) => {
                                             if cfg!(debug_assertions) {
                                                 trace!("failed to collect {} {} {}: {:?}", memory_error_type_str(&err), ecc_counter_type_str(&ecc), memory_location_str(&loc), e);
@@ -390,6 +1052,44 @@ fn gather(ctx: Arc<Context>) -> Result<(), NvmlError> {
             }
             Err(e) => warn!("could not check ECC state, skipping memory error metrics: {:?}", e),
         }
+
+        // page retirement and row remapping can be queried even if ECC is reported disabled
+        timed!("memory_health", {
+            for cause in [RetirementCause::MultipleSingleBitEccErrors, RetirementCause::DoubleBitEccError] {
+                match device.retired_pages(cause.clone()) {
+                    Ok(pages) => {
+                        set_gv!(ctx.metrics.gv_retired_pages, &[dev_idx_str, dev_uuid, retirement_cause_str(&cause)], pages.len());
+                    }
+                    Err(e) => {
+                        if cfg!(debug_assertions) {
+                            trace!("failed to collect retired pages for {}: {:?}", retirement_cause_str(&cause), e);
+                        }
+                    }
+                }
+            }
+            if let Ok(pending) = device.are_pages_pending_retired() {
+                set_gv!(ctx.metrics.gv_retired_pages_pending, dl, if pending { 1 } else { 0 });
+            }
+
+            match ctx.raw.remapped_rows(&device) {
+                Ok(rows) => {
+                    set_gv!(ctx.metrics.gv_remapped_rows, &[dev_idx_str, dev_uuid, "correctable"], rows.correctable);
+                    set_gv!(ctx.metrics.gv_remapped_rows, &[dev_idx_str, dev_uuid, "uncorrectable"], rows.uncorrectable);
+                    set_gv!(ctx.metrics.gv_remapped_rows_pending, dl, if rows.pending { 1 } else { 0 });
+                    set_gv!(ctx.metrics.gv_remapped_rows_failure, dl, if rows.failure { 1 } else { 0 });
+                }
+                Err(e) => {
+                    if cfg!(debug_assertions) {
+                        trace!("failed to collect remapped rows: {:?}", e);
+                    }
+                }
+            }
+            if let Ok(histogram) = ctx.raw.row_remapper_histogram(&device) {
+                for (availability, banks) in [("max", histogram.max), ("high", histogram.high), ("partial", histogram.partial), ("low", histogram.low), ("none", histogram.none)] {
+                    set_gv!(ctx.metrics.gv_row_remapper_histogram, &[dev_idx_str, dev_uuid, availability], banks);
+                }
+            }
+        });
     }
     debug!("NVML metrics gather took {}ms", now.elapsed().unwrap().as_millis());
     Ok(())
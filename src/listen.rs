@@ -0,0 +1,485 @@
+#[cfg(unix)]
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+#[cfg(target_os = "linux")]
+use std::os::fd::FromRawFd;
+#[cfg(target_os = "linux")]
+use std::os::fd::OwnedFd;
+#[cfg(target_os = "linux")]
+use std::os::fd::RawFd;
+#[cfg(unix)]
+use std::os::unix::fs::DirBuilderExt;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[cfg(unix)]
+use log::debug;
+use log::warn;
+#[cfg(target_os = "linux")]
+use nix::sys::socket::getsockname;
+#[cfg(target_os = "linux")]
+use nix::sys::socket::getsockopt;
+#[cfg(target_os = "linux")]
+use nix::sys::socket::sockopt;
+#[cfg(target_os = "linux")]
+use nix::sys::socket::AddressFamily;
+#[cfg(target_os = "linux")]
+use nix::sys::socket::SockaddrLike;
+#[cfg(target_os = "linux")]
+use nix::sys::socket::SockaddrStorage;
+use socket2::Domain;
+use socket2::Protocol;
+use socket2::Socket;
+use socket2::Type;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+use crate::web::Io;
+
+/// Backlog of TCP listeners, as used by the standard library.
+const TCP_BACKLOG: i32 = 128;
+
+/// A `--listen` value: a TCP socket address, `HOST:PORT` resolved when binding, or `unix:PATH`
+/// for a Unix domain socket. On Linux, also a listening socket passed by systemd socket activation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Host(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+    #[cfg(target_os = "linux")]
+    Systemd {
+        fd: RawFd,
+        name: String,
+    },
+}
+
+/// Error setting up a listener.
+#[derive(Debug)]
+pub enum Error {
+    /// A `--listen` value that is neither an address with a port nor `unix:PATH`.
+    InvalidAddress {
+        addr: String,
+        reason: &'static str,
+    },
+    Resolve {
+        addr: String,
+        source: io::Error,
+    },
+    /// Binding `addr` would conflict with the wildcard address `wildcard` on the same port.
+    Overlap {
+        addr: ListenAddr,
+        wildcard: ListenAddr,
+    },
+    Bind {
+        addr: ListenAddr,
+        source: io::Error,
+    },
+    /// None of the listen addresses could be bound.
+    NoListeners,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidAddress { addr, reason } => write!(f, "invalid listen address {}: {}", addr, reason),
+            Error::Resolve { addr, source } => write!(f, "could not resolve {}: {}", addr, source),
+            Error::Overlap { addr, wildcard } => write!(f, "not listening on {}, {} already listens on that port", addr, wildcard),
+            Error::Bind { addr, source } => write!(f, "could not listen on {}: {}", addr, source),
+            Error::NoListeners => write!(f, "could not listen on any address"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Resolve { source, .. } | Error::Bind { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ListenAddr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason| Error::InvalidAddress { addr: s.to_string(), reason };
+        if let Some(path) = s.strip_prefix("unix:") {
+            return match path {
+                "" => Err(invalid("missing unix socket path")),
+                #[cfg(unix)]
+                path => Ok(ListenAddr::Unix(PathBuf::from(path))),
+                #[cfg(not(unix))]
+                _ => Err(invalid("unix sockets are not supported on this platform")),
+            };
+        }
+        if let Ok(addr) = SocketAddr::from_str(s) {
+            return Ok(ListenAddr::Tcp(addr));
+        }
+        match s.rsplit_once(':') {
+            // IPv6 addresses need brackets, e.g. [::1]:9996
+            Some((host, port)) if !host.is_empty() && !host.contains(':') => match port.parse::<u16>() {
+                Ok(_) => Ok(ListenAddr::Host(s.to_string())),
+                Err(_) => Err(invalid("invalid port")),
+            },
+            _ => Err(invalid("expected HOST:PORT, IP:PORT, [IPV6]:PORT or unix:PATH")),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => addr.fmt(f),
+            ListenAddr::Host(host) => host.fmt(f),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+            #[cfg(target_os = "linux")]
+            ListenAddr::Systemd { fd, name } => write!(f, "systemd socket {} (fd {})", name, fd),
+        }
+    }
+}
+
+/// Permissions applied to Unix domain sockets after binding them.
+#[cfg(unix)]
+#[derive(Clone, Debug, Default)]
+pub struct UnixSocketOptions {
+    pub mode: Option<u32>,
+    pub owner: Option<Owner>,
+}
+
+/// `USER[:GROUP]`, by name or numeric ID. Either part may be empty to keep it unchanged.
+#[cfg(unix)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Owner {
+    uid: Option<u32>,
+    gid: Option<u32>,
+}
+
+#[cfg(unix)]
+impl FromStr for Owner {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, group) = s.split_once(':').unwrap_or((s, ""));
+        let uid = match user {
+            "" => None,
+            user => Some(match user.parse() {
+                Ok(uid) => uid,
+                Err(_) => nix::unistd::User::from_name(user).map_err(|e| e.to_string())?.ok_or_else(|| format!("unknown user {user}"))?.uid.as_raw(),
+            }),
+        };
+        let gid = match group {
+            "" => None,
+            group => Some(match group.parse() {
+                Ok(gid) => gid,
+                Err(_) => nix::unistd::Group::from_name(group).map_err(|e| e.to_string())?.ok_or_else(|| format!("unknown group {group}"))?.gid.as_raw(),
+            }),
+        };
+        Ok(Owner { uid, gid })
+    }
+}
+
+/// Parse an octal file mode such as `0660`.
+#[cfg(unix)]
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    match u32::from_str_radix(s, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err("must be an octal file mode, e.g. 0660".to_string()),
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        _socket_file: SocketFile,
+    },
+}
+
+impl Listener {
+    pub async fn accept(&self) -> io::Result<Box<dyn Io>> {
+        match self {
+            Listener::Tcp(listener) => Ok(Box::new(listener.accept().await?.0)),
+            #[cfg(unix)]
+            Listener::Unix { listener, .. } => Ok(Box::new(listener.accept().await?.0)),
+        }
+    }
+}
+
+/// Resolve and bind `addrs`, returning the listeners that could be set up along with the value
+/// paired with their address, and the errors of the others. Must be called from within a Tokio runtime.
+pub async fn bind_all<T: Clone>(addrs: Vec<(ListenAddr, T)>, #[cfg(unix)] unix_socket: &UnixSocketOptions) -> (Vec<(ListenAddr, Listener, T)>, Vec<Error>) {
+    let mut errors = vec![];
+    let mut resolved: Vec<(ListenAddr, T)> = vec![];
+    for (addr, value) in addrs {
+        let addrs = match &addr {
+            ListenAddr::Host(host) => match tokio::net::lookup_host(host.as_str()).await {
+                Ok(addrs) => addrs.map(ListenAddr::Tcp).collect(),
+                Err(source) => {
+                    errors.push(Error::Resolve { addr: host.clone(), source });
+                    continue;
+                }
+            },
+            _ => vec![addr],
+        };
+        for addr in addrs {
+            if resolved.iter().any(|(other, _)| *other == addr) {
+                warn!("{} is listed more than once, listening on it once", addr);
+                continue;
+            }
+            resolved.push((addr, value.clone()));
+        }
+    }
+
+    let tcp = resolved
+        .iter()
+        .filter_map(|(addr, _)| match addr {
+            ListenAddr::Tcp(addr) => Some(*addr),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let mut listeners = vec![];
+    for (addr, value) in resolved {
+        let mut only_v6 = false;
+        if let ListenAddr::Tcp(socket_addr) = addr {
+            if let Some(wildcard) = tcp.iter().find(|wildcard| covers(wildcard, &socket_addr)) {
+                errors.push(Error::Overlap { addr, wildcard: ListenAddr::Tcp(*wildcard) });
+                continue;
+            }
+            // with dual-stack sockets, an IPv6 wildcard would also take the port of the IPv4 listeners
+            only_v6 = socket_addr.is_ipv6() && socket_addr.port() != 0 && tcp.iter().any(|other| other.is_ipv4() && other.port() == socket_addr.port());
+        }
+        match addr.bind(
+            only_v6,
+            #[cfg(unix)]
+            unix_socket,
+        ) {
+            Ok(listener) => listeners.push((addr, listener, value)),
+            Err(source) => errors.push(Error::Bind { addr, source }),
+        }
+    }
+    (listeners, errors)
+}
+
+/// Whether listening on the wildcard address `wildcard` takes the port of `addr`.
+fn covers(wildcard: &SocketAddr, addr: &SocketAddr) -> bool {
+    wildcard.ip().is_unspecified() && !addr.ip().is_unspecified() && wildcard.is_ipv4() == addr.is_ipv4() && wildcard.port() == addr.port() && addr.port() != 0
+}
+
+impl ListenAddr {
+    pub fn bind(&self, only_v6: bool, #[cfg(unix)] unix_socket: &UnixSocketOptions) -> io::Result<Listener> {
+        match self {
+            ListenAddr::Tcp(addr) => {
+                let socket = Socket::new(Domain::for_address(*addr), Type::STREAM, Some(Protocol::TCP))?;
+                if only_v6 {
+                    socket.set_only_v6(true)?;
+                }
+                // like the standard library, so that restarts do not fail on connections in TIME_WAIT
+                #[cfg(unix)]
+                socket.set_reuse_address(true)?;
+                socket.bind(&(*addr).into())?;
+                socket.listen(TCP_BACKLOG)?;
+                socket.set_nonblocking(true)?;
+                Ok(Listener::Tcp(TcpListener::from_std(socket.into())?))
+            }
+            ListenAddr::Host(host) => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not resolved", host))),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                remove_stale_socket(path)?;
+                let listener = bind_unix(path, unix_socket)?;
+                Ok(Listener::Unix {
+                    listener,
+                    _socket_file: SocketFile(Some(path.clone())),
+                })
+            }
+            #[cfg(target_os = "linux")]
+            ListenAddr::Systemd { fd, .. } => {
+                // SAFETY: systemd passes the listening sockets as the file descriptors following
+                // stdio, nothing else in the exporter owns them.
+                let fd = unsafe { OwnedFd::from_raw_fd(*fd) };
+                if !getsockopt(&fd, sockopt::AcceptConn)? {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a listening socket"));
+                }
+                match getsockname::<SockaddrStorage>(fd.as_raw_fd())?.family() {
+                    Some(AddressFamily::Inet | AddressFamily::Inet6) => {
+                        let listener = std::net::TcpListener::from(fd);
+                        listener.set_nonblocking(true)?;
+                        Ok(Listener::Tcp(TcpListener::from_std(listener)?))
+                    }
+                    Some(AddressFamily::Unix) => {
+                        let listener = std::os::unix::net::UnixListener::from(fd);
+                        listener.set_nonblocking(true)?;
+                        Ok(Listener::Unix {
+                            listener: UnixListener::from_std(listener)?,
+                            _socket_file: SocketFile(None),
+                        })
+                    }
+                    family => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unsupported socket family {:?}", family))),
+                }
+            }
+        }
+    }
+}
+
+/// Bind a Unix socket in a private directory next to `path` and move it into place once its mode
+/// and owner are set, so that it is never reachable with the permissions derived from the umask.
+#[cfg(unix)]
+fn bind_unix(path: &Path, options: &UnixSocketOptions) -> io::Result<UnixListener> {
+    let file_name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a file path", path.display())))?;
+    let mut dir_name = OsString::from(".");
+    dir_name.push(file_name);
+    dir_name.push(format!(".{}", std::process::id()));
+    let dir = path.with_file_name(dir_name);
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        // left behind by a previous instance with the same PID, e.g. in a container
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            std::fs::remove_dir_all(&dir)?;
+            std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+        }
+        result => result?,
+    }
+
+    let private_path = dir.join(file_name);
+    let bind = || {
+        let listener = UnixListener::bind(&private_path)?;
+        if let Some(mode) = options.mode {
+            std::fs::set_permissions(&private_path, std::fs::Permissions::from_mode(mode))?;
+        }
+        if let Some(owner) = &options.owner {
+            std::os::unix::fs::chown(&private_path, owner.uid, owner.gid)?;
+        }
+        std::fs::rename(&private_path, path)?;
+        Ok(listener)
+    };
+    let result = bind();
+    if let Err(e) = std::fs::remove_dir_all(&dir) {
+        warn!("could not remove {}: {}", dir.display(), e);
+    }
+    result
+}
+
+/// Removes the socket file once the listener is closed. `None` for sockets passed by systemd, which manages them.
+#[cfg(unix)]
+pub struct SocketFile(Option<PathBuf>);
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Some(path) = &self.0 {
+            if let Err(e) = std::fs::remove_file(path) {
+                warn!("could not remove socket {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Remove a socket left behind by a previous instance that did not shut down cleanly, refusing
+/// to remove files that are not sockets or sockets another process still accepts connections on.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path.display())));
+    }
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{} is in use by another process", path.display()))),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            debug!("removing stale socket {}", path.display());
+            std::fs::remove_file(path)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_listen_addresses() {
+        assert_eq!("127.0.0.1:9996".parse().ok(), Some(ListenAddr::Tcp(SocketAddr::from(([127, 0, 0, 1], 9996)))));
+        assert_eq!("localhost:9996".parse().ok(), Some(ListenAddr::Host("localhost:9996".to_string())));
+        assert_eq!("unix:/run/nvml-exporter.sock".parse().ok(), Some(ListenAddr::Unix(PathBuf::from("/run/nvml-exporter.sock"))));
+        for invalid in ["unix:", "localhost", "localhost:http", ":9996", "::1:9996", "[::1]"] {
+            assert!(matches!(invalid.parse::<ListenAddr>(), Err(Error::InvalidAddress { .. })), "{}", invalid);
+        }
+        assert_eq!("0:".parse(), Ok(Owner { uid: Some(0), gid: None }));
+        assert_eq!(":0".parse(), Ok(Owner { uid: None, gid: Some(0) }));
+        assert_eq!(parse_mode("0660"), Ok(0o660));
+        assert!(parse_mode("0999").is_err());
+    }
+
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("[::]:0").unwrap().local_addr().unwrap().port()
+    }
+
+    #[tokio::test]
+    async fn binds_what_it_can() {
+        if std::net::TcpListener::bind("[::]:0").is_err() {
+            eprintln!("skipping binds_what_it_can, IPv6 is not available");
+            return;
+        }
+        let (port, other_port) = (free_port(), free_port());
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = |addr: String| addr.parse::<ListenAddr>().unwrap();
+        let addrs = vec![
+            (addr(format!("[::]:{port}")), "v6"),
+            (addr(format!("0.0.0.0:{port}")), "v4"),
+            (addr(format!("0.0.0.0:{port}")), "duplicate"),
+            (addr(format!("127.0.0.1:{port}")), "overlap"),
+            (addr(format!("127.0.0.1:{other_port}")), "other"),
+            (addr(taken.local_addr().unwrap().to_string()), "taken"),
+        ];
+
+        let (listeners, errors) = bind_all(addrs, &Default::default()).await;
+        let bound = listeners.iter().map(|(addr, _, value)| (addr.to_string(), *value)).collect::<Vec<_>>();
+        assert_eq!(bound, [(format!("[::]:{port}"), "v6"), (format!("0.0.0.0:{port}"), "v4"), (format!("127.0.0.1:{other_port}"), "other")]);
+        assert!(matches!(&errors[..], [Error::Overlap { .. }, Error::Bind { .. }]), "{:?}", errors);
+    }
+
+    #[tokio::test]
+    async fn replaces_stale_sockets_only() {
+        let dir = std::env::temp_dir().join(format!("nvml-exporter-unix-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("exporter.sock");
+        let addr = ListenAddr::Unix(path.clone());
+        let options = UnixSocketOptions { mode: Some(0o600), owner: None };
+
+        // left behind by a previous instance
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let listener = addr.bind(false, &options).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o7777, 0o600);
+        // the private directory the socket was bound in is gone
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        assert_eq!(addr.bind(false, &options).err().unwrap().kind(), io::ErrorKind::AddrInUse);
+
+        drop(listener);
+        assert!(!path.exists());
+
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(addr.bind(false, &options).err().unwrap().kind(), io::ErrorKind::AlreadyExists);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
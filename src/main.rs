@@ -9,7 +9,7 @@ fn main() -> anyhow::Result<()> {
         Box::new(|shutdown_rx: std::sync::mpsc::Receiver<()>, args: Vec<String>, _start_parameters: Option<Vec<String>>| -> anyhow::Result<()> {
             let rt = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
             let handle = rt.handle().clone();
-            let (binds, senders, options) = nvml_exporter::server_setup(args);
+            let (binds, senders, options) = nvml_exporter::server_setup(args)?;
             std::thread::spawn(move || {
                 handle.block_on(async move {
                     let _ = shutdown_rx.recv();
@@ -18,7 +18,7 @@ fn main() -> anyhow::Result<()> {
                     });
                 });
             });
-            rt.block_on(nvml_exporter::serve(binds, options));
+            rt.block_on(nvml_exporter::serve(binds, options))?;
             Ok(())
         }),
     )?;
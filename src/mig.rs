@@ -0,0 +1,120 @@
+use std::collections::BTreeSet;
+
+use log::trace;
+use log::warn;
+use nvml::error::NvmlError;
+use nvml::Device;
+
+use crate::ffi::RawNvml;
+use crate::gpm;
+use crate::Metrics;
+
+/// Extract the MIG profile from a MIG device name, e.g. `1g.5gb` from `NVIDIA A100-SXM4-40GB MIG 1g.5gb`.
+fn profile_name(name: &str) -> &str {
+    match name.rsplit_once("MIG ") {
+        Some((_, profile)) => profile.trim(),
+        None => name,
+    }
+}
+
+/// Export MIG mode and, when enabled, memory, processes and identity of each MIG device of `device`,
+/// and GPM metrics of each of its GPU instances.
+pub fn collect(metrics: &Metrics, raw: &RawNvml, gpm_samples: &gpm::Samples, device: &Device, dl: &[&str; 2]) {
+    let mode = match raw.mig_mode(device) {
+        Ok(mode) => mode,
+        Err(NvmlError::NotSupported) | Err(NvmlError::FailedToLoadSymbol(_)) => {
+            trace!("MIG is not supported by device {} or its driver", dl[0]);
+            return;
+        }
+        Err(e) => {
+            warn!("could not check MIG mode, skipping MIG metrics: {:?}", e);
+            return;
+        }
+    };
+    set_gv!(metrics.gv_mig_mode, &[dl[0], dl[1], "current"], if mode.current { 1 } else { 0 });
+    set_gv!(metrics.gv_mig_mode, &[dl[0], dl[1], "pending"], if mode.pending { 1 } else { 0 });
+    if !mode.current {
+        return;
+    }
+
+    let max_count = match raw.max_mig_device_count(device) {
+        Ok(count) => count,
+        Err(e) => {
+            warn!("error fetching max MIG device count: {:?}", e);
+            return;
+        }
+    };
+    // several compute instances can share a GPU instance
+    let mut gpu_instance_ids = BTreeSet::new();
+    for mig_index in 0..max_count {
+        let mig_device = match raw.mig_device_by_index(device, mig_index) {
+            Ok(mig_device) => mig_device,
+            Err(NvmlError::NotFound) => continue,
+            Err(e) => {
+                warn!("error fetching MIG device {} of device {}: {:?}", mig_index, dl[0], e);
+                continue;
+            }
+        };
+        match collect_mig_device(metrics, raw, &mig_device, dl) {
+            Ok(gpu_instance_id) => {
+                gpu_instance_ids.insert(gpu_instance_id);
+            }
+            Err(e) => warn!("error collecting MIG device {} of device {}: {:?}", mig_index, dl[0], e),
+        }
+    }
+    gpm::collect_mig(metrics, raw, gpm_samples, device, dl, &gpu_instance_ids);
+}
+
+/// Export a MIG device, returning the ID of its GPU instance.
+fn collect_mig_device(metrics: &Metrics, raw: &RawNvml, mig_device: &Device, dl: &[&str; 2]) -> Result<u32, NvmlError> {
+    let mig_uuid = mig_device.uuid()?;
+    let gpu_instance_id = raw.gpu_instance_id(mig_device)?;
+    let gi_id = gpu_instance_id.to_string();
+    let ci_id = raw.compute_instance_id(mig_device)?.to_string();
+    let name = mig_device.name()?;
+    let ml = [dl[0], dl[1], mig_uuid.as_str(), gi_id.as_str(), ci_id.as_str(), profile_name(&name)];
+
+    set_gv!(metrics.gv_mig_device_info, &ml, 1);
+
+    match mig_device.memory_info() {
+        Ok(mem) => {
+            for (state, value) in [("free", mem.free), ("total", mem.total), ("used", mem.used)] {
+                let labels = &[ml[0], ml[1], ml[2], ml[3], ml[4], ml[5], state];
+                set_gv!(metrics.gv_mig_memory_info, labels, value);
+            }
+        }
+        Err(e) => warn!("error fetching MIG memory info: {:?}", e),
+    }
+
+    match raw.attributes(mig_device) {
+        Ok(attributes) => {
+            set_gv!(metrics.gv_mig_multiprocessor_count, &ml, attributes.multiprocessorCount);
+            set_gv!(metrics.gv_mig_gpu_instance_slice_count, &ml, attributes.gpuInstanceSliceCount);
+            set_gv!(metrics.gv_mig_compute_instance_slice_count, &ml, attributes.computeInstanceSliceCount);
+        }
+        Err(e) => warn!("error fetching MIG device attributes: {:?}", e),
+    }
+
+    match mig_device.running_compute_processes_count() {
+        Ok(count) => {
+            set_gv!(metrics.gv_mig_running_compute_processes_count, &ml, count);
+        }
+        Err(e) => warn!("error fetching MIG running compute processes: {:?}", e),
+    }
+
+    Ok(gpu_instance_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_profile_names() {
+        assert_eq!(profile_name("NVIDIA A100-SXM4-40GB MIG 1g.5gb"), "1g.5gb");
+        assert_eq!(profile_name("NVIDIA H100 80GB HBM3 MIG 1g.10gb+me"), "1g.10gb+me");
+        assert_eq!(profile_name("NVIDIA A30 MIG 2g.12gb "), "2g.12gb");
+        // names without a profile are kept whole
+        assert_eq!(profile_name("NVIDIA A100-SXM4-40GB"), "NVIDIA A100-SXM4-40GB");
+    }
+}
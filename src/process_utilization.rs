@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use log::warn;
+use nvml::error::NvmlError;
+use nvml::struct_wrappers::device::ProcessUtilizationSample;
+use nvml::Device;
+
+use crate::Metrics;
+
+/// Timestamp of the newest process utilization sample seen so far, per device UUID.
+#[derive(Default)]
+pub struct LastSeen(Mutex<HashMap<String, u64>>);
+
+/// Utilization of a process averaged over several samples.
+#[derive(Default)]
+struct Average {
+    samples: u32,
+    sm: u64,
+    mem: u64,
+    enc: u64,
+    dec: u64,
+}
+
+impl Average {
+    fn add(&mut self, sample: &ProcessUtilizationSample) {
+        self.samples += 1;
+        self.sm += sample.sm_util as u64;
+        self.mem += sample.mem_util as u64;
+        self.enc += sample.enc_util as u64;
+        self.dec += sample.dec_util as u64;
+    }
+
+    fn of(&self, sum: u64) -> f64 {
+        sum as f64 / self.samples as f64
+    }
+}
+
+/// Average `samples` per process, along with the timestamp to query newer samples from: that of
+/// the newest sample, or `since` when there is none.
+fn averages(samples: &[ProcessUtilizationSample], since: Option<u64>) -> (HashMap<u32, Average>, Option<u64>) {
+    let mut averages: HashMap<u32, Average> = HashMap::new();
+    for sample in samples {
+        averages.entry(sample.pid).or_default().add(sample);
+    }
+    let newest = samples.iter().map(|s| s.timestamp).max().max(since);
+    (averages, newest)
+}
+
+/// Export per-process utilization averaged over the samples taken since the previous collection.
+pub fn collect(metrics: &Metrics, last_seen: &LastSeen, device: &Device, dl: &[&str; 2]) {
+    let mut last_seen = last_seen.0.lock().unwrap();
+    let since = last_seen.get(dl[1]).copied();
+    let samples = match device.process_utilization_stats(since) {
+        Ok(samples) => samples,
+        // no process was active since the last timestamp
+        Err(NvmlError::NotFound) => vec![],
+        Err(e) => {
+            warn!("error collecting process utilization stats: {:?}", e);
+            return;
+        }
+    };
+
+    let (averages, newest) = averages(&samples, since);
+    if let Some(newest) = newest {
+        last_seen.insert(dl[1].to_string(), newest);
+    }
+
+    for (pid, avg) in averages {
+        let pid = pid.to_string();
+        let labels = &[dl[0], dl[1], pid.as_str()];
+        set_gv!(metrics.gv_process_utilization_sm, labels, avg.of(avg.sm));
+        set_gv!(metrics.gv_process_utilization_memory, labels, avg.of(avg.mem));
+        set_gv!(metrics.gv_process_utilization_encoder, labels, avg.of(avg.enc));
+        set_gv!(metrics.gv_process_utilization_decoder, labels, avg.of(avg.dec));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(pid: u32, timestamp: u64, sm_util: u32, mem_util: u32) -> ProcessUtilizationSample {
+        ProcessUtilizationSample {
+            pid,
+            timestamp,
+            sm_util,
+            mem_util,
+            enc_util: 0,
+            dec_util: 0,
+        }
+    }
+
+    #[test]
+    fn averages_samples_per_process() {
+        let (averages, newest) = averages(&[sample(1, 100, 10, 4), sample(2, 300, 50, 0), sample(1, 200, 30, 8)], Some(50));
+        assert_eq!(averages.len(), 2);
+        assert_eq!((averages[&1].samples, averages[&1].of(averages[&1].sm), averages[&1].of(averages[&1].mem)), (2, 20., 6.));
+        assert_eq!((averages[&2].samples, averages[&2].of(averages[&2].sm)), (1, 50.));
+        assert_eq!(newest, Some(300));
+    }
+
+    #[test]
+    fn keeps_timestamp_without_samples() {
+        let (averages, newest) = averages(&[], Some(300));
+        assert!(averages.is_empty());
+        assert_eq!(newest, Some(300));
+        assert_eq!(super::averages(&[], None).1, None);
+    }
+}
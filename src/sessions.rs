@@ -0,0 +1,46 @@
+use log::{trace, warn};
+use nvml::error::NvmlError;
+use nvml::Device;
+
+use crate::ffi::RawNvml;
+use crate::str_helpers::*;
+use crate::Metrics;
+
+/// Export per-session encoder and frame buffer capture details, up to `max_sessions` sessions of each kind per device.
+pub fn collect(metrics: &Metrics, raw: &RawNvml, device: &Device, dl: &[&str; 2], max_sessions: usize) {
+    match raw.encoder_sessions(device) {
+        Ok(sessions) => {
+            set_gv!(metrics.gv_sessions_omitted, &[dl[0], dl[1], "encoder"], sessions.len().saturating_sub(max_sessions));
+            for session in sessions.iter().take(max_sessions) {
+                let session_id = session.sessionId.to_string();
+                let pid = session.pid.to_string();
+                let labels = &[dl[0], dl[1], session_id.as_str(), pid.as_str(), encoder_codec_str(session.codecType)];
+                set_gv!(metrics.gv_encoder_session_fps, labels, session.averageFps);
+                set_gv!(metrics.gv_encoder_session_latency, labels, session.averageLatency);
+                set_gv!(metrics.gv_encoder_session_hres, labels, session.hResolution);
+                set_gv!(metrics.gv_encoder_session_vres, labels, session.vResolution);
+            }
+        }
+        Err(NvmlError::NotSupported) | Err(NvmlError::FailedToLoadSymbol(_)) => {
+            trace!("encoder sessions are not supported by device {} or its driver", dl[0]);
+        }
+        Err(e) => warn!("error collecting encoder sessions: {:?}", e),
+    }
+
+    match device.fbc_sessions_info() {
+        Ok(sessions) => {
+            set_gv!(metrics.gv_sessions_omitted, &[dl[0], dl[1], "fbc"], sessions.len().saturating_sub(max_sessions));
+            for session in sessions.iter().take(max_sessions) {
+                let session_id = session.session_id.to_string();
+                let pid = session.pid.to_string();
+                let labels = &[dl[0], dl[1], session_id.as_str(), pid.as_str(), fbc_session_type_str(&session.session_type)];
+                set_gv!(metrics.gv_fbc_session_fps, labels, session.average_fps);
+                set_gv!(metrics.gv_fbc_session_latency, labels, session.average_latency);
+                set_gv!(metrics.gv_fbc_session_hres, labels, session.hres);
+                set_gv!(metrics.gv_fbc_session_vres, labels, session.vres);
+            }
+        }
+        Err(NvmlError::NotSupported) => trace!("frame buffer capture sessions are not supported by device {}", dl[0]),
+        Err(e) => warn!("error collecting frame buffer capture sessions: {:?}", e),
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+
+use log::trace;
+use log::warn;
+use nvml::enums::device::UsedGpuMemory;
+use nvml::struct_wrappers::device::ProcessInfo;
+use nvml::Device;
+
+use crate::Metrics;
+
+/// Slurm job owning a GPU process, as discovered from procfs.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SlurmJob {
+    pub job_id: String,
+    pub user: String,
+    pub partition: String,
+}
+
+/// User names by uid, since looking them up can be slow with LDAP or SSSD. Only names that were
+/// found are kept, so that a failed lookup is retried on the next collection.
+#[derive(Default)]
+pub struct UserNames(Mutex<HashMap<u32, String>>);
+
+impl UserNames {
+    fn get(&self, uid: u32) -> String {
+        if let Some(name) = self.0.lock().unwrap().get(&uid) {
+            return name.clone();
+        }
+        match user_name(uid) {
+            Some(name) => {
+                self.0.lock().unwrap().insert(uid, name.clone());
+                name
+            }
+            None => uid.to_string(),
+        }
+    }
+}
+
+/// Find the Slurm job a process belongs to.
+///
+/// The cgroup path (`/slurm/uid_X/job_Y/`) is authoritative for the job ID and user, since it cannot
+/// be altered by the job itself; the environment fills in the partition name. The user is always
+/// resolved from its uid, so that a job is labeled the same whether or not its environment is readable.
+pub fn job_for_pid(pid: u32, user_names: &UserNames) -> Option<SlurmJob> {
+    let cgroup = fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok();
+    let environ = fs::read(format!("/proc/{}/environ", pid)).ok();
+    let env = environ.as_deref().map(parse_environ).unwrap_or_default();
+
+    let (uid, job_id) = match cgroup.as_deref().and_then(parse_cgroup) {
+        Some((uid, job_id)) => (uid, job_id),
+        None => (None, env.get("SLURM_JOB_ID")?.clone()),
+    };
+    let uid = uid.or_else(|| env.get("SLURM_JOB_UID")?.parse().ok()).or_else(|| process_uid(pid));
+    let user = uid.map(|uid| user_names.get(uid)).unwrap_or_default();
+    let partition = env.get("SLURM_JOB_PARTITION").cloned().unwrap_or_default();
+
+    Some(SlurmJob { job_id, user, partition })
+}
+
+/// Extract `(uid, job_id)` from the contents of `/proc/<pid>/cgroup`, the uid only being part of cgroup v1 paths.
+fn parse_cgroup(contents: &str) -> Option<(Option<u32>, String)> {
+    contents.lines().find_map(|line| {
+        let path = line.splitn(3, ':').nth(2)?;
+        // cgroup v1: `/slurm/uid_X/job_Y/...`, cgroup v2: `/system.slice/slurmstepd.scope/job_Y/...`
+        let mut components = path.split('/').skip_while(|c| !c.starts_with("slurm"));
+        components.next()?;
+        let mut uid = None;
+        for c in components {
+            if let Some(u) = c.strip_prefix("uid_") {
+                uid = u.parse().ok();
+            } else if let Some(job) = c.strip_prefix("job_") {
+                return Some((uid, job.to_string()));
+            }
+        }
+        None
+    })
+}
+
+/// Owner of the process, for jobs whose cgroup does not include the uid.
+#[cfg(unix)]
+fn process_uid(pid: u32) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(format!("/proc/{}", pid)).ok().map(|metadata| metadata.uid())
+}
+
+#[cfg(not(unix))]
+fn process_uid(_pid: u32) -> Option<u32> {
+    None
+}
+
+/// Name of the user with `uid`, `None` if it has no passwd entry.
+#[cfg(unix)]
+fn user_name(uid: u32) -> Option<String> {
+    match nix::unistd::User::from_uid(nix::unistd::Uid::from_raw(uid)) {
+        Ok(user) => user.map(|user| user.name),
+        Err(e) => {
+            warn!("could not look up the name of uid {}: {}", uid, e);
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn user_name(_uid: u32) -> Option<String> {
+    None
+}
+
+/// Parse the NUL-separated `KEY=value` pairs of `/proc/<pid>/environ`, keeping only Slurm variables.
+fn parse_environ(contents: &[u8]) -> HashMap<String, String> {
+    contents
+        .split(|b| *b == 0)
+        .filter_map(|kv| {
+            let kv = String::from_utf8_lossy(kv);
+            let (k, v) = kv.split_once('=')?;
+            k.starts_with("SLURM_").then(|| (k.to_string(), v.to_string()))
+        })
+        .collect()
+}
+
+/// Attribute the processes running on `device` to Slurm jobs and export the job join metric and per-job memory totals.
+pub fn collect(metrics: &Metrics, user_names: &UserNames, device: &Device, dl: &[&str; 2]) {
+    let mut processes: Vec<ProcessInfo> = vec![];
+    match device.running_compute_processes() {
+        Ok(mut p) => processes.append(&mut p),
+        Err(e) => warn!("error collecting running compute processes: {:?}", e),
+    }
+    match device.running_graphics_processes() {
+        Ok(mut p) => processes.append(&mut p),
+        Err(e) => warn!("error collecting running graphics processes: {:?}", e),
+    }
+
+    // processes using both compute and graphics show up in both lists
+    let mut used_by_pid: HashMap<u32, u64> = HashMap::new();
+    for process in processes {
+        let used = match process.used_gpu_memory {
+            UsedGpuMemory::Used(bytes) => bytes,
+            UsedGpuMemory::Unavailable => 0,
+        };
+        used_by_pid.insert(process.pid, used);
+    }
+
+    let mut jobs: HashMap<SlurmJob, u64> = HashMap::new();
+    for (pid, used) in used_by_pid {
+        match job_for_pid(pid, user_names) {
+            Some(job) => *jobs.entry(job).or_default() += used,
+            None => trace!("pid {} on device {} is not part of a slurm job", pid, dl[0]),
+        }
+    }
+
+    for (job, used) in jobs {
+        let labels = &[dl[0], dl[1], job.job_id.as_str(), job.user.as_str(), job.partition.as_str()];
+        set_gv!(metrics.gv_device_job_info, labels, 1);
+        set_gv!(metrics.gv_job_memory_used, labels, used);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cgroup_paths() {
+        assert_eq!(parse_cgroup("4:memory:/slurm/uid_1000/job_42/step_0/task_0\n"), Some((Some(1000), "42".to_string())));
+        assert_eq!(parse_cgroup("0::/system.slice/slurmstepd.scope/job_42/step_0/user/task_0\n"), Some((None, "42".to_string())));
+        assert_eq!(parse_cgroup("0::/user.slice/user-1000.slice/session-1.scope\n"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn names_users_by_uid() {
+        let user_names = UserNames::default();
+        assert_eq!(user_names.get(0), "root");
+        // no passwd entry
+        assert_eq!(user_names.get(4_000_000_000), "4000000000");
+        assert_eq!(*user_names.0.lock().unwrap(), HashMap::from([(0, "root".to_string())]));
+    }
+}
@@ -0,0 +1,619 @@
+use std::collections::BTreeMap;
+
+use chrono::SecondsFormat;
+use nvml::enum_wrappers::device::ClockId;
+use nvml::enum_wrappers::device::EccCounter;
+use nvml::enum_wrappers::device::MemoryError;
+use nvml::enum_wrappers::device::TemperatureSensor;
+use nvml::error::NvmlError;
+use nvml::Device;
+use serde::Serialize;
+
+use crate::clocks::ClockSource;
+use crate::clocks::CLOCK_TYPES;
+use crate::str_helpers::*;
+use crate::Context;
+use crate::MEMORY_LOCATIONS;
+use crate::PERFORMANCE_POLICIES;
+use crate::THROTTLE_REASONS;
+
+/// Version of the JSON schema, bumped whenever a field is renamed, removed or changes meaning.
+/// Adding fields does not change the version.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// State of every device, served at `/api/v1/devices`.
+#[derive(Debug, Serialize)]
+pub struct Snapshot {
+    pub schema_version: u32,
+    /// When the collection started, in RFC 3339 format.
+    pub timestamp: String,
+    pub devices: Vec<DeviceSnapshot>,
+}
+
+/// State of a single device, served at `/api/v1/devices/{uuid}`.
+#[derive(Debug, Serialize)]
+pub struct SingleSnapshot {
+    pub schema_version: u32,
+    pub timestamp: String,
+    pub device: DeviceSnapshot,
+}
+
+/// Body of error responses.
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub schema_version: u32,
+    pub error: String,
+}
+
+/// Values that could not be queried are `null`, with the error in `errors` keyed by query. A device
+/// that cannot be accessed at all only has its index, and the error under `device_by_index`.
+///
+/// This covers the state of the device itself. Processes, sessions, MIG and vGPU instances,
+/// topology, retired pages, remapped rows and field values are only exported as metrics.
+#[derive(Debug, Default, Serialize)]
+pub struct DeviceSnapshot {
+    pub index: u32,
+    pub uuid: Option<String>,
+    pub name: Option<String>,
+    pub serial: Option<String>,
+    pub pci_bus_id: Option<String>,
+    pub temperatures: Temperatures,
+    pub power_usage_watts: Option<f64>,
+    /// Energy consumed since the driver was loaded, Volta and newer.
+    pub energy_joules: Option<f64>,
+    pub utilization: Option<Utilization>,
+    pub encoder_utilization: Option<CodecUtilization>,
+    pub decoder_utilization: Option<CodecUtilization>,
+    pub processes: Processes,
+    pub pcie: Pcie,
+    /// Keyed by clock type, `graphics`, `sm`, `mem` or `video` like the `type` label of the clock metrics.
+    pub clocks: BTreeMap<&'static str, Clocks>,
+    pub memory: Option<Memory>,
+    pub bar1_memory: Option<Memory>,
+    /// Active throttle reasons, named like the `reason` label of `nvml_current_clocks_throttle_reasons`
+    /// and empty when the clocks are not throttled.
+    pub throttle_reasons: Option<Vec<&'static str>>,
+    /// Time during which clocks were limited by each performance policy since the driver was
+    /// loaded, keyed like the `reason` label of `nvml_throttle_violation_seconds_total`.
+    pub violation_seconds: BTreeMap<&'static str, f64>,
+    pub modes: Modes,
+    pub ecc: Ecc,
+    pub errors: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Temperatures {
+    pub gpu_celsius: Option<u32>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Utilization {
+    pub gpu_percent: u32,
+    pub memory_percent: u32,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CodecUtilization {
+    pub percent: u32,
+    pub sampling_period_us: u32,
+}
+
+/// Number of running processes.
+#[derive(Debug, Default, Serialize)]
+pub struct Processes {
+    pub compute: Option<u32>,
+    pub graphics: Option<u32>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Pcie {
+    pub current_link_generation: Option<u32>,
+    pub current_link_width: Option<u32>,
+    pub max_link_generation: Option<u32>,
+    pub max_link_width: Option<u32>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Modes {
+    /// `default`, `exclusive_thread`, `prohibited` or `exclusive_process`.
+    pub compute: Option<&'static str>,
+    /// Always `null` outside of Linux.
+    pub persistence: Option<bool>,
+    pub accounting: Option<bool>,
+    pub display_active: Option<bool>,
+    pub display_connected: Option<bool>,
+    pub auto_boost: Option<AutoBoost>,
+    pub gpu_operation: Option<OperationModes>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AutoBoost {
+    pub current: bool,
+    pub default: bool,
+}
+
+/// GPU operation modes, `all_on`, `compute` or `low_dp`.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct OperationModes {
+    pub current: &'static str,
+    pub pending: &'static str,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Clocks {
+    pub current_mhz: Option<u32>,
+    pub applications_mhz: Option<u32>,
+    pub default_applications_mhz: Option<u32>,
+    pub max_mhz: Option<u32>,
+    pub customer_boost_max_mhz: Option<u32>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Memory {
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+    pub used_bytes: u64,
+    /// Memory reserved by the driver, only reported for the framebuffer by drivers from R510.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reserved_bytes: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Ecc {
+    pub current_enabled: Option<bool>,
+    pub pending_enabled: Option<bool>,
+    /// Error counts, only collected while ECC is enabled.
+    pub counters: Vec<EccCount>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct EccCount {
+    /// `corrected` or `uncorrected`.
+    pub error_type: &'static str,
+    /// `volatile` since the last driver load or `aggregate` over the lifetime of the device.
+    pub counter: &'static str,
+    pub location: &'static str,
+    pub count: u64,
+}
+
+impl Snapshot {
+    pub fn collect(ctx: &Context) -> Result<Snapshot, NvmlError> {
+        let timestamp = now();
+        let devices = (0..ctx.nvml.device_count()?)
+            .map(|index| match ctx.nvml.device_by_index(index) {
+                Ok(device) => DeviceSnapshot::collect(ctx, index, &device),
+                Err(e) => DeviceSnapshot {
+                    index,
+                    errors: BTreeMap::from([("device_by_index".to_string(), e.to_string())]),
+                    ..Default::default()
+                },
+            })
+            .collect();
+        Ok(Snapshot {
+            schema_version: SCHEMA_VERSION,
+            timestamp,
+            devices,
+        })
+    }
+}
+
+impl SingleSnapshot {
+    /// Snapshot of the device with `uuid`, `Ok(None)` if there is no such device.
+    pub fn collect(ctx: &Context, uuid: &str) -> Result<Option<SingleSnapshot>, NvmlError> {
+        let timestamp = now();
+        let device = match ctx.nvml.device_by_uuid(uuid) {
+            Ok(device) => device,
+            Err(NvmlError::NotFound | NvmlError::InvalidArg) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(Some(SingleSnapshot {
+            schema_version: SCHEMA_VERSION,
+            timestamp,
+            device: DeviceSnapshot::collect(ctx, device.index()?, &device),
+        }))
+    }
+}
+
+impl ErrorResponse {
+    pub fn new(error: impl ToString) -> Self {
+        ErrorResponse {
+            schema_version: SCHEMA_VERSION,
+            error: error.to_string(),
+        }
+    }
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+impl DeviceSnapshot {
+    /// Query the state of the device that `gather()` exports.
+    fn collect(ctx: &Context, index: u32, device: &Device) -> DeviceSnapshot {
+        let mut snapshot = DeviceSnapshot { index, ..Default::default() };
+        let errors = &mut snapshot.errors;
+
+        snapshot.uuid = query(errors, "uuid", device.uuid());
+        snapshot.name = query(errors, "name", device.name());
+        snapshot.serial = query(errors, "serial", device.serial());
+        snapshot.pci_bus_id = query(errors, "pci_info", device.pci_info()).map(|pci| pci.bus_id);
+        snapshot.temperatures.gpu_celsius = query(errors, "temperature.gpu", device.temperature(TemperatureSensor::Gpu));
+        snapshot.power_usage_watts = query(errors, "power_usage", device.power_usage()).map(|milliwatts| milliwatts as f64 / 1000.);
+        snapshot.energy_joules = query(errors, "total_energy_consumption", device.total_energy_consumption()).map(|millijoules| millijoules as f64 / 1000.);
+        snapshot.utilization = query(errors, "utilization_rates", device.utilization_rates()).map(|util| Utilization {
+            gpu_percent: util.gpu,
+            memory_percent: util.memory,
+        });
+        snapshot.encoder_utilization = query(errors, "encoder_utilization", device.encoder_utilization()).map(|util| CodecUtilization {
+            percent: util.utilization,
+            sampling_period_us: util.sampling_period,
+        });
+        snapshot.decoder_utilization = query(errors, "decoder_utilization", device.decoder_utilization()).map(|util| CodecUtilization {
+            percent: util.utilization,
+            sampling_period_us: util.sampling_period,
+        });
+        snapshot.processes.compute = query(errors, "processes.compute", device.running_compute_processes_count());
+        snapshot.processes.graphics = query(errors, "processes.graphics", device.running_graphics_processes_count());
+        snapshot.pcie = Pcie {
+            current_link_generation: query(errors, "pcie.current_link_generation", device.current_pcie_link_gen()),
+            current_link_width: query(errors, "pcie.current_link_width", device.current_pcie_link_width()),
+            max_link_generation: query(errors, "pcie.max_link_generation", device.max_pcie_link_gen()),
+            max_link_width: query(errors, "pcie.max_link_width", device.max_pcie_link_width()),
+        };
+        snapshot.clocks = clocks(errors, device);
+
+        snapshot.memory = match ctx.raw.memory_info_v2(device) {
+            // v2 excludes reserved memory from used, v1 includes it; keep the v1 meaning like the metrics
+            Ok(mem) => Some(Memory {
+                total_bytes: mem.total,
+                free_bytes: mem.free,
+                used_bytes: mem.used + mem.reserved,
+                reserved_bytes: Some(mem.reserved),
+            }),
+            // drivers before R510 only have v1, so the v2 error only matters if v1 fails too
+            Err(v2_error) => {
+                let memory = query(errors, "memory_info", device.memory_info()).map(|mem| Memory {
+                    total_bytes: mem.total,
+                    free_bytes: mem.free,
+                    used_bytes: mem.used,
+                    reserved_bytes: None,
+                });
+                if memory.is_none() {
+                    errors.insert("memory_info_v2".to_string(), v2_error.to_string());
+                }
+                memory
+            }
+        };
+        snapshot.bar1_memory = query(errors, "bar1_memory_info", device.bar1_memory_info()).map(|bar1| Memory {
+            total_bytes: bar1.total,
+            free_bytes: bar1.free,
+            used_bytes: bar1.used,
+            reserved_bytes: None,
+        });
+
+        snapshot.throttle_reasons =
+            query(errors, "throttle_reasons", device.current_throttle_reasons()).map(|active| THROTTLE_REASONS.into_iter().filter(|reason| !reason.is_empty() && active.contains(*reason)).map(throttle_reason_str).collect());
+
+        for policy in PERFORMANCE_POLICIES {
+            let reason = performance_policy_str(&policy);
+            // like ECC counters, unsupported policies are left out rather than reported as errors
+            match device.violation_status(policy) {
+                Ok(violation) => {
+                    snapshot.violation_seconds.insert(reason, violation.violation_time as f64 / 1e9);
+                }
+                Err(NvmlError::NotSupported) => (),
+                Err(e) => {
+                    errors.insert(format!("violation_status.{}", reason), e.to_string());
+                }
+            }
+        }
+
+        snapshot.modes.compute = query(errors, "modes.compute", device.compute_mode()).map(|mode| compute_mode_str(&mode));
+        #[cfg(target_os = "linux")]
+        {
+            snapshot.modes.persistence = query(errors, "modes.persistence", device.is_in_persistent_mode());
+        }
+        snapshot.modes.accounting = query(errors, "modes.accounting", device.is_accounting_enabled());
+        snapshot.modes.display_active = query(errors, "modes.display_active", device.is_display_active());
+        snapshot.modes.display_connected = query(errors, "modes.display_connected", device.is_display_connected());
+        snapshot.modes.auto_boost = query(errors, "modes.auto_boost", device.auto_boosted_clocks_enabled()).map(|auto_boost| AutoBoost {
+            current: auto_boost.is_enabled,
+            default: auto_boost.is_enabled_default,
+        });
+        snapshot.modes.gpu_operation = query(errors, "modes.gpu_operation", device.gpu_operation_mode()).map(|state| OperationModes {
+            current: operation_mode_str(&state.current),
+            pending: operation_mode_str(&state.pending),
+        });
+
+        if let Some(ecc_state) = query(errors, "ecc_mode", device.is_ecc_enabled()) {
+            snapshot.ecc.current_enabled = Some(ecc_state.currently_enabled);
+            snapshot.ecc.pending_enabled = Some(ecc_state.pending_enabled);
+            if ecc_state.currently_enabled {
+                for loc in MEMORY_LOCATIONS {
+                    for ecc in [EccCounter::Aggregate, EccCounter::Volatile] {
+                        for err in [MemoryError::Corrected, MemoryError::Uncorrected] {
+                            let (error_type, counter, location) = (memory_error_type_str(&err), ecc_counter_type_str(&ecc), memory_location_str(&loc));
+                            // most locations only exist on some architectures, so unsupported ones are left out rather than reported as errors
+                            match device.memory_error_counter(err.clone(), ecc.clone(), loc.clone()) {
+                                Ok(count) => snapshot.ecc.counters.push(EccCount { error_type, counter, location, count }),
+                                Err(NvmlError::NotSupported) => (),
+                                Err(e) => {
+                                    errors.insert(format!("memory_error_counter.{}.{}.{}", error_type, counter, location), e.to_string());
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        snapshot
+    }
+}
+
+/// The value of a query, or `None` with its error recorded under `name`.
+fn query<T>(errors: &mut BTreeMap<String, String>, name: &str, result: Result<T, NvmlError>) -> Option<T> {
+    result.map_err(|e| errors.insert(name.to_string(), e.to_string())).ok()
+}
+
+fn clocks(errors: &mut BTreeMap<String, String>, device: &impl ClockSource) -> BTreeMap<&'static str, Clocks> {
+    let mut clocks = BTreeMap::new();
+    for ctype in CLOCK_TYPES {
+        let ctype_str = clock_type_str(ctype.clone());
+        let mut query = |name: &str, result| query(errors, &format!("clocks.{}.{}", ctype_str, name), result);
+        let type_clocks = Clocks {
+            current_mhz: query("current", device.clock(ctype.clone(), ClockId::Current)),
+            applications_mhz: query("applications", device.applications_clock(ctype.clone())),
+            default_applications_mhz: query("default_applications", device.default_applications_clock(ctype.clone())),
+            max_mhz: query("max", device.max_clock_info(ctype.clone())),
+            customer_boost_max_mhz: query("customer_boost_max", device.max_customer_boost_clock(ctype.clone())),
+        };
+        clocks.insert(ctype_str, type_clocks);
+    }
+    clocks
+}
+
+#[cfg(test)]
+mod tests {
+    use nvml::enum_wrappers::device::Clock;
+    use serde_json::json;
+
+    use super::*;
+
+    /// Device whose graphics and SM clocks are supported but have no customer boost.
+    struct FakeClocks;
+
+    impl ClockSource for FakeClocks {
+        fn clock(&self, clock_type: Clock, _clock_id: ClockId) -> Result<u32, NvmlError> {
+            match clock_type {
+                Clock::Graphics | Clock::SM => Ok(1200),
+                Clock::Memory => Ok(5000),
+                Clock::Video => Err(NvmlError::NotSupported),
+            }
+        }
+
+        fn applications_clock(&self, clock_type: Clock) -> Result<u32, NvmlError> {
+            self.clock(clock_type, ClockId::TargetAppClock).map(|clock| clock + 300)
+        }
+
+        fn default_applications_clock(&self, clock_type: Clock) -> Result<u32, NvmlError> {
+            self.clock(clock_type, ClockId::DefaultAppClock).map(|clock| clock + 200)
+        }
+
+        fn max_clock_info(&self, clock_type: Clock) -> Result<u32, NvmlError> {
+            self.clock(clock_type, ClockId::Current).map(|clock| clock + 800)
+        }
+
+        fn max_customer_boost_clock(&self, _clock_type: Clock) -> Result<u32, NvmlError> {
+            Err(NvmlError::NotSupported)
+        }
+
+        fn supported_memory_clocks(&self) -> Result<Vec<u32>, NvmlError> {
+            Ok(vec![5000])
+        }
+
+        fn supported_graphics_clocks(&self, _for_mem_clock: u32) -> Result<Vec<u32>, NvmlError> {
+            Ok(vec![1500, 1200])
+        }
+    }
+
+    #[test]
+    fn records_query_errors() {
+        let mut errors = BTreeMap::new();
+        let clocks = clocks(&mut errors, &FakeClocks);
+
+        assert_eq!(clocks.keys().copied().collect::<Vec<_>>(), ["graphics", "mem", "sm", "video"]);
+        assert_eq!(
+            clocks["graphics"],
+            Clocks {
+                current_mhz: Some(1200),
+                applications_mhz: Some(1500),
+                default_applications_mhz: Some(1400),
+                max_mhz: Some(2000),
+                customer_boost_max_mhz: None,
+            }
+        );
+        assert_eq!(clocks["video"], Clocks::default());
+        assert_eq!(errors["clocks.sm.customer_boost_max"], NvmlError::NotSupported.to_string());
+        assert!(errors.contains_key("clocks.video.current"));
+        assert!(!errors.keys().any(|query| query.starts_with("clocks.graphics.") && !query.ends_with(".customer_boost_max")));
+    }
+
+    /// Version 1 of the schema. Changing this test other than by adding fields requires bumping [`SCHEMA_VERSION`].
+    #[test]
+    fn schema_v1() {
+        let device = DeviceSnapshot {
+            index: 0,
+            uuid: Some("GPU-8ac2d3a8-0bb8-4c7f-9a5e-2d3b6c1e9f00".to_string()),
+            name: Some("NVIDIA A100-SXM4-40GB".to_string()),
+            serial: Some("1320321012345".to_string()),
+            pci_bus_id: Some("00000000:07:00.0".to_string()),
+            temperatures: Temperatures { gpu_celsius: Some(34) },
+            power_usage_watts: Some(61.512),
+            energy_joules: Some(1234567.891),
+            utilization: Some(Utilization { gpu_percent: 87, memory_percent: 42 }),
+            encoder_utilization: Some(CodecUtilization { percent: 0, sampling_period_us: 167000 }),
+            decoder_utilization: Some(CodecUtilization { percent: 12, sampling_period_us: 167000 }),
+            processes: Processes { compute: Some(2), graphics: Some(0) },
+            pcie: Pcie {
+                current_link_generation: Some(4),
+                current_link_width: Some(16),
+                max_link_generation: Some(4),
+                max_link_width: Some(16),
+            },
+            clocks: BTreeMap::from([(
+                "graphics",
+                Clocks {
+                    current_mhz: Some(1410),
+                    applications_mhz: Some(1095),
+                    default_applications_mhz: Some(1095),
+                    max_mhz: Some(1410),
+                    customer_boost_max_mhz: None,
+                },
+            )]),
+            memory: Some(Memory {
+                total_bytes: 42949672960,
+                free_bytes: 42314629120,
+                used_bytes: 635043840,
+                reserved_bytes: Some(630849536),
+            }),
+            bar1_memory: Some(Memory {
+                total_bytes: 68719476736,
+                free_bytes: 68717379584,
+                used_bytes: 2097152,
+                reserved_bytes: None,
+            }),
+            throttle_reasons: Some(vec!["sw_power_cap"]),
+            violation_seconds: BTreeMap::from([("power", 12.5), ("thermal", 0.)]),
+            modes: Modes {
+                compute: Some("default"),
+                persistence: Some(true),
+                accounting: Some(false),
+                display_active: Some(false),
+                display_connected: Some(false),
+                auto_boost: None,
+                gpu_operation: Some(OperationModes { current: "all_on", pending: "all_on" }),
+            },
+            ecc: Ecc {
+                current_enabled: Some(true),
+                pending_enabled: Some(true),
+                counters: vec![EccCount {
+                    error_type: "corrected",
+                    counter: "volatile",
+                    location: "device",
+                    count: 3,
+                }],
+            },
+            errors: BTreeMap::from([
+                ("clocks.graphics.customer_boost_max".to_string(), "the requested operation is not available on the target device".to_string()),
+                ("modes.auto_boost".to_string(), "the requested operation is not available on the target device".to_string()),
+            ]),
+        };
+        let device_json = json!({
+            "index": 0,
+            "uuid": "GPU-8ac2d3a8-0bb8-4c7f-9a5e-2d3b6c1e9f00",
+            "name": "NVIDIA A100-SXM4-40GB",
+            "serial": "1320321012345",
+            "pci_bus_id": "00000000:07:00.0",
+            "temperatures": {"gpu_celsius": 34},
+            "power_usage_watts": 61.512,
+            "energy_joules": 1234567.891,
+            "utilization": {"gpu_percent": 87, "memory_percent": 42},
+            "encoder_utilization": {"percent": 0, "sampling_period_us": 167000},
+            "decoder_utilization": {"percent": 12, "sampling_period_us": 167000},
+            "processes": {"compute": 2, "graphics": 0},
+            "pcie": {"current_link_generation": 4, "current_link_width": 16, "max_link_generation": 4, "max_link_width": 16},
+            "clocks": {
+                "graphics": {
+                    "current_mhz": 1410,
+                    "applications_mhz": 1095,
+                    "default_applications_mhz": 1095,
+                    "max_mhz": 1410,
+                    "customer_boost_max_mhz": null,
+                },
+            },
+            "memory": {"total_bytes": 42949672960u64, "free_bytes": 42314629120u64, "used_bytes": 635043840, "reserved_bytes": 630849536},
+            "bar1_memory": {"total_bytes": 68719476736u64, "free_bytes": 68717379584u64, "used_bytes": 2097152},
+            "throttle_reasons": ["sw_power_cap"],
+            "violation_seconds": {"power": 12.5, "thermal": 0.0},
+            "modes": {
+                "compute": "default",
+                "persistence": true,
+                "accounting": false,
+                "display_active": false,
+                "display_connected": false,
+                "auto_boost": null,
+                "gpu_operation": {"current": "all_on", "pending": "all_on"},
+            },
+            "ecc": {
+                "current_enabled": true,
+                "pending_enabled": true,
+                "counters": [{"error_type": "corrected", "counter": "volatile", "location": "device", "count": 3}],
+            },
+            "errors": {
+                "clocks.graphics.customer_boost_max": "the requested operation is not available on the target device",
+                "modes.auto_boost": "the requested operation is not available on the target device",
+            },
+        });
+
+        let snapshot = Snapshot {
+            schema_version: SCHEMA_VERSION,
+            timestamp: "2024-05-01T12:00:00.000Z".to_string(),
+            devices: vec![device],
+        };
+        assert_eq!(
+            serde_json::to_value(&snapshot).unwrap(),
+            json!({"schema_version": 1, "timestamp": "2024-05-01T12:00:00.000Z", "devices": [device_json.clone()]})
+        );
+
+        let single = SingleSnapshot {
+            schema_version: SCHEMA_VERSION,
+            timestamp: snapshot.timestamp,
+            device: snapshot.devices.into_iter().next().unwrap(),
+        };
+        assert_eq!(serde_json::to_value(&single).unwrap(), json!({"schema_version": 1, "timestamp": "2024-05-01T12:00:00.000Z", "device": device_json}));
+    }
+
+    /// Queries that failed are `null` rather than left out, so that clients see every field of the schema.
+    #[test]
+    fn failed_queries_are_null() {
+        // e.g. a device lost since the driver was loaded
+        let device = serde_json::to_value(DeviceSnapshot {
+            index: 1,
+            errors: BTreeMap::from([("device_by_index".to_string(), NvmlError::GpuLost.to_string())]),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(device["index"], 1);
+        for field in [
+            "uuid",
+            "name",
+            "serial",
+            "pci_bus_id",
+            "power_usage_watts",
+            "energy_joules",
+            "utilization",
+            "encoder_utilization",
+            "decoder_utilization",
+            "memory",
+            "bar1_memory",
+            "throttle_reasons",
+        ] {
+            assert_eq!(device[field], serde_json::Value::Null, "{}", field);
+        }
+        assert_eq!(device["temperatures"], json!({"gpu_celsius": null}));
+        assert_eq!(device["processes"], json!({"compute": null, "graphics": null}));
+        assert_eq!(device["pcie"], json!({"current_link_generation": null, "current_link_width": null, "max_link_generation": null, "max_link_width": null}));
+        assert_eq!(device["violation_seconds"], json!({}));
+        assert_eq!(
+            device["modes"],
+            json!({"compute": null, "persistence": null, "accounting": null, "display_active": null, "display_connected": null, "auto_boost": null, "gpu_operation": null})
+        );
+        assert_eq!(device["ecc"], json!({"current_enabled": null, "pending_enabled": null, "counters": []}));
+        assert_eq!(device["errors"], json!({"device_by_index": NvmlError::GpuLost.to_string()}));
+
+        assert_eq!(
+            serde_json::to_value(ErrorResponse::new("no device with UUID GPU-0")).unwrap(),
+            json!({"schema_version": 1, "error": "no device with UUID GPU-0"})
+        );
+    }
+}
@@ -1,9 +1,24 @@
 use nvml::bitmasks::device::ThrottleReasons;
 use nvml::enum_wrappers::device::Clock;
 use nvml::enum_wrappers::device::ClockId;
+use nvml::enum_wrappers::device::ComputeMode;
+#[cfg(target_os = "windows")]
+use nvml::enum_wrappers::device::DriverModel;
 use nvml::enum_wrappers::device::EccCounter;
+use nvml::enum_wrappers::device::FbcSessionType;
 use nvml::enum_wrappers::device::MemoryError;
 use nvml::enum_wrappers::device::MemoryLocation;
+use nvml::enum_wrappers::device::OperationMode;
+#[cfg(target_os = "linux")]
+use nvml::enum_wrappers::device::P2pStatus;
+use nvml::enum_wrappers::device::PerformancePolicy;
+use nvml::enum_wrappers::device::RetirementCause;
+#[cfg(target_os = "linux")]
+use nvml::enum_wrappers::device::TopologyLevel;
+use nvml_wrapper_sys::bindings::nvmlEncoderQueryType_enum_NVML_ENCODER_QUERY_H264;
+use nvml_wrapper_sys::bindings::nvmlEncoderQueryType_enum_NVML_ENCODER_QUERY_HEVC;
+
+use crate::ffi::ENCODER_QUERY_AV1;
 
 pub fn clock_id_str(cid: ClockId) -> &'static str {
     match cid {
@@ -65,3 +80,94 @@ pub fn memory_location_str(m: &MemoryLocation) -> &'static str {
         MemoryLocation::Texture => "texture",
     }
 }
+
+/// Codec of an encoder session, from the raw `nvmlEncoderType_t`.
+#[allow(non_upper_case_globals)]
+pub fn encoder_codec_str(codec: u32) -> &'static str {
+    match codec {
+        nvmlEncoderQueryType_enum_NVML_ENCODER_QUERY_H264 => "h264",
+        nvmlEncoderQueryType_enum_NVML_ENCODER_QUERY_HEVC => "hevc",
+        ENCODER_QUERY_AV1 => "av1",
+        _ => "unknown",
+    }
+}
+
+pub fn fbc_session_type_str(t: &FbcSessionType) -> &'static str {
+    match t {
+        FbcSessionType::Unknown => "unknown",
+        FbcSessionType::ToSys => "tosys",
+        FbcSessionType::Cuda => "cuda",
+        FbcSessionType::Vid => "vid",
+        FbcSessionType::HwEnc => "hwenc",
+    }
+}
+
+pub fn retirement_cause_str(c: &RetirementCause) -> &'static str {
+    match c {
+        RetirementCause::MultipleSingleBitEccErrors => "multiple_single_bit_ecc_errors",
+        RetirementCause::DoubleBitEccError => "double_bit_ecc_error",
+    }
+}
+
+pub fn performance_policy_str(p: &PerformancePolicy) -> &'static str {
+    match p {
+        PerformancePolicy::Power => "power",
+        PerformancePolicy::Thermal => "thermal",
+        PerformancePolicy::SyncBoost => "sync_boost",
+        PerformancePolicy::BoardLimit => "board_limit",
+        PerformancePolicy::LowUtilization => "low_utilization",
+        PerformancePolicy::Reliability => "reliability",
+        PerformancePolicy::TotalAppClocks => "total_app_clocks",
+        PerformancePolicy::TotalBaseClocks => "total_base_clocks",
+    }
+}
+
+pub fn compute_mode_str(m: &ComputeMode) -> &'static str {
+    match m {
+        ComputeMode::Default => "default",
+        ComputeMode::ExclusiveThread => "exclusive_thread",
+        ComputeMode::Prohibited => "prohibited",
+        ComputeMode::ExclusiveProcess => "exclusive_process",
+    }
+}
+
+pub fn operation_mode_str(m: &OperationMode) -> &'static str {
+    match m {
+        OperationMode::AllOn => "all_on",
+        OperationMode::Compute => "compute",
+        OperationMode::LowDP => "low_dp",
+    }
+}
+
+#[cfg(target_os = "windows")]
+pub fn driver_model_str(m: &DriverModel) -> &'static str {
+    match m {
+        DriverModel::WDDM => "wddm",
+        DriverModel::WDM => "wdm",
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn topology_level_str(l: &TopologyLevel) -> &'static str {
+    match l {
+        TopologyLevel::Internal => "internal",
+        TopologyLevel::Single => "single",
+        TopologyLevel::Multiple => "multiple",
+        TopologyLevel::HostBridge => "hostbridge",
+        TopologyLevel::Node => "node",
+        TopologyLevel::System => "system",
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn p2p_status_str(s: &P2pStatus) -> &'static str {
+    match s {
+        P2pStatus::Ok => "ok",
+        P2pStatus::ChipsetNotSupported => "chipset_not_supported",
+        P2pStatus::GpuNotSupported => "gpu_not_supported",
+        P2pStatus::IohTopologyNotSupported => "ioh_topology_not_supported",
+        P2pStatus::DisabledByRegkey => "disabled_by_regkey",
+        P2pStatus::NotSupported => "not_supported",
+        P2pStatus::Unknown => "unknown",
+    }
+}
@@ -0,0 +1,179 @@
+use std::ffi::OsStr;
+use std::io;
+use std::os::fd::BorrowedFd;
+use std::os::fd::RawFd;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::SocketAddr;
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::info;
+use log::warn;
+use nix::fcntl::fcntl;
+use nix::fcntl::FcntlArg;
+use nix::fcntl::FdFlag;
+use tokio::time::MissedTickBehavior;
+
+use crate::listen::ListenAddr;
+use crate::web;
+use crate::Context;
+
+/// First file descriptor passed by systemd socket activation, see `sd_listen_fds(3)`.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Listeners passed by systemd socket activation, empty when not socket activated.
+///
+/// Like `sd_listen_fds(3)`, the passed descriptors are marked close-on-exec and the variables
+/// describing them are removed, so that child processes inherit neither.
+pub fn listen_fds() -> Vec<ListenAddr> {
+    let var = |name| std::env::var(name).ok();
+    let pid = var("LISTEN_PID");
+    let count = var("LISTEN_FDS");
+    let names = var("LISTEN_FDNAMES").unwrap_or_default();
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(name);
+    }
+    if pid.and_then(|pid| pid.parse::<u32>().ok()) != Some(std::process::id()) {
+        return vec![];
+    }
+    let count = count.and_then(|count| count.parse::<RawFd>().ok()).unwrap_or(0);
+    let mut names = names.split(':');
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            // SAFETY: systemd passes `count` open descriptors starting at `LISTEN_FDS_START`.
+            if let Err(e) = fcntl(unsafe { BorrowedFd::borrow_raw(fd) }, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)) {
+                warn!("could not set close-on-exec on inherited socket {}: {}", fd, e);
+            }
+            ListenAddr::Systemd {
+                fd,
+                name: names.next().filter(|name| !name.is_empty()).unwrap_or("unknown").to_string(),
+            }
+        })
+        .collect()
+}
+
+/// Send a state such as `READY=1` to the service manager, if running under systemd with `Type=notify`.
+pub fn notify(state: &str) {
+    if let Some(path) = std::env::var_os("NOTIFY_SOCKET") {
+        if let Err(e) = send(&path, state) {
+            warn!("could not notify systemd of {}: {}", state, e);
+        }
+    }
+}
+
+fn send(path: &OsStr, state: &str) -> io::Result<()> {
+    let addr = match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+/// Watchdog timeout configured with `WatchdogSec=`, if any.
+pub fn watchdog_timeout() -> Option<Duration> {
+    if let Some(pid) = std::env::var_os("WATCHDOG_PID") {
+        if pid.to_str().and_then(|pid| pid.parse::<u32>().ok()) != Some(std::process::id()) {
+            return None;
+        }
+    }
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Ping the watchdog at half of `timeout` while collections keep succeeding, collecting in the
+/// background when no scrape did so recently. A collection hanging in NVML stops the pings, so
+/// that systemd restarts the service.
+pub async fn watchdog(ctx: Arc<Context>, timeout: Duration) {
+    let period = timeout / 2;
+    info!("notifying the systemd watchdog every {:?}", period);
+    let mut ticks = tokio::time::interval(period);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        if ctx.shutdown.load(Ordering::Relaxed) {
+            break;
+        }
+        if !web::collected_within(&ctx, period) {
+            let collecting = ctx.clone();
+            tokio::task::spawn_blocking(move || web::collect(&collecting)).await.ok();
+            if !web::collected_within(&ctx, period) {
+                warn!("collection failed, not notifying the systemd watchdog");
+                continue;
+            }
+        }
+        notify("WATCHDOG=1");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::fd::IntoRawFd;
+
+    use super::*;
+    use crate::listen::Listener;
+
+    #[test]
+    fn notifies_path_and_abstract_sockets() {
+        let path = std::env::temp_dir().join(format!("nvml-exporter-notify-{}.sock", std::process::id()));
+        let socket = UnixDatagram::bind(&path).unwrap();
+        send(path.as_os_str(), "READY=1").unwrap();
+        let mut buffer = [0; 64];
+        let len = socket.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"READY=1");
+        std::fs::remove_file(path).unwrap();
+
+        let name = format!("nvml-exporter-notify-{}", std::process::id());
+        let socket = UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(name.as_bytes()).unwrap()).unwrap();
+        send(OsStr::new(&format!("@{name}")), "WATCHDOG=1").unwrap();
+        let len = socket.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"WATCHDOG=1");
+    }
+
+    #[test]
+    fn removes_listen_variables() {
+        // passed to another process, e.g. the parent of a forking service
+        std::env::set_var("LISTEN_PID", "1");
+        std::env::set_var("LISTEN_FDS", "1");
+        std::env::set_var("LISTEN_FDNAMES", "metrics");
+        assert!(listen_fds().is_empty());
+        for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            assert!(std::env::var_os(name).is_none(), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn inherits_listeners() {
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let local_addr = tcp.local_addr().unwrap();
+        let addr = ListenAddr::Systemd {
+            fd: tcp.into_raw_fd(),
+            name: "metrics".to_string(),
+        };
+        match addr.bind(false, &Default::default()).unwrap() {
+            Listener::Tcp(listener) => assert_eq!(listener.local_addr().unwrap(), local_addr),
+            _ => panic!("expected a TCP listener"),
+        }
+
+        let path = std::env::temp_dir().join(format!("nvml-exporter-inherited-{}.sock", std::process::id()));
+        let unix = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let addr = ListenAddr::Systemd {
+            fd: unix.into_raw_fd(),
+            name: "metrics".to_string(),
+        };
+        assert!(matches!(addr.bind(false, &Default::default()).unwrap(), Listener::Unix { .. }));
+        // the socket belongs to systemd, which removes it
+        assert!(path.exists());
+        std::fs::remove_file(path).unwrap();
+
+        let socket = UnixDatagram::unbound().unwrap();
+        let addr = ListenAddr::Systemd {
+            fd: socket.into_raw_fd(),
+            name: "metrics".to_string(),
+        };
+        assert!(addr.bind(false, &Default::default()).is_err());
+    }
+}
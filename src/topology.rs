@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::os::raw::c_ulong;
+use std::sync::OnceLock;
+
+use log::debug;
+use log::trace;
+use log::warn;
+use nvml::enum_wrappers::device::P2pStatus;
+use nvml::error::NvmlError;
+use nvml::Device;
+use nvml::Nvml;
+use nvml_wrapper_sys::bindings::nvmlGpuP2PCapsIndex_enum_NVML_P2P_CAPS_INDEX_ATOMICS;
+use nvml_wrapper_sys::bindings::nvmlGpuP2PCapsIndex_enum_NVML_P2P_CAPS_INDEX_NVLINK;
+use nvml_wrapper_sys::bindings::nvmlGpuP2PCapsIndex_enum_NVML_P2P_CAPS_INDEX_READ;
+use nvml_wrapper_sys::bindings::nvmlGpuP2PCapsIndex_enum_NVML_P2P_CAPS_INDEX_WRITE;
+use nvml_wrapper_sys::bindings::nvmlGpuP2PCapsIndex_t;
+
+use crate::ffi::RawNvml;
+use crate::str_helpers::*;
+use crate::Metrics;
+
+/// Number of words queried for CPU and NUMA node affinity, enough for 4096 CPUs.
+const AFFINITY_WORDS: usize = 64;
+
+const P2P_CAPABILITIES: [(&str, nvmlGpuP2PCapsIndex_t); 4] = [
+    ("read", nvmlGpuP2PCapsIndex_enum_NVML_P2P_CAPS_INDEX_READ),
+    ("write", nvmlGpuP2PCapsIndex_enum_NVML_P2P_CAPS_INDEX_WRITE),
+    ("nvlink", nvmlGpuP2PCapsIndex_enum_NVML_P2P_CAPS_INDEX_NVLINK),
+    ("atomics", nvmlGpuP2PCapsIndex_enum_NVML_P2P_CAPS_INDEX_ATOMICS),
+];
+
+/// Connection of a device to one of its peers.
+struct Link {
+    peer: String,
+    level: Option<&'static str>,
+    p2p: Vec<(&'static str, P2pStatus)>,
+}
+
+/// Links between all pairs of devices, keyed by device UUID.
+///
+/// Probing every pair is quadratic in the number of devices and the topology does not change at
+/// runtime, so it is discovered once on the first successful gather.
+#[derive(Default)]
+pub struct Topology(OnceLock<HashMap<String, Vec<Link>>>);
+
+/// Indices of the bits set in an affinity bitmask.
+fn set_bits(mask: &[c_ulong]) -> impl Iterator<Item = usize> + '_ {
+    let bits = c_ulong::BITS as usize;
+    mask.iter()
+        .enumerate()
+        .flat_map(move |(word_index, word)| (0..bits).filter(move |bit| word & (1 << bit) != 0).map(move |bit| word_index * bits + bit))
+}
+
+/// Format an affinity bitmask as a list of ranges like `/sys/devices/system/cpu/online`, e.g. `0-15,32-47`.
+fn range_list(mask: &[c_ulong]) -> String {
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for bit in set_bits(mask) {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == bit => *end = bit,
+            _ => ranges.push((bit, bit)),
+        }
+    }
+    ranges.iter().map(|(start, end)| if start == end { start.to_string() } else { format!("{start}-{end}") }).collect::<Vec<_>>().join(",")
+}
+
+fn discover(nvml: &Nvml, raw: &RawNvml) -> Result<HashMap<String, Vec<Link>>, NvmlError> {
+    let count = nvml.device_count()?;
+    let mut topology = HashMap::with_capacity(count as usize);
+    for device_index in 0..count {
+        let device = nvml.device_by_index(device_index)?;
+        let mut links = vec![];
+        for peer_index in (0..count).filter(|peer_index| *peer_index != device_index) {
+            let peer = nvml.device_by_index(peer_index)?;
+            let mut p2p = vec![];
+            for (capability, index) in P2P_CAPABILITIES {
+                match raw.p2p_status(&device, &peer, index) {
+                    Ok(status) => p2p.push((capability, status)),
+                    Err(e) => trace!("failed to collect P2P {} status of devices {} and {}: {:?}", capability, device_index, peer_index, e),
+                }
+            }
+
+            // like `nvidia-smi topo -m`, NVLink takes precedence over the common PCIe ancestor
+            let level = if p2p.contains(&("nvlink", P2pStatus::Ok)) {
+                Some("nvlink")
+            } else {
+                match device.topology_common_ancestor(peer) {
+                    Ok(level) => Some(topology_level_str(&level)),
+                    Err(e) => {
+                        warn!("error fetching common ancestor of devices {} and {}: {:?}", device_index, peer_index, e);
+                        None
+                    }
+                }
+            };
+            links.push(Link { peer: peer_index.to_string(), level, p2p });
+        }
+        topology.insert(device.uuid()?, links);
+    }
+    debug!("discovered topology of {} devices", count);
+    Ok(topology)
+}
+
+/// Export the CPU and NUMA affinity of `device` and its cached links to every other device.
+pub fn collect(metrics: &Metrics, nvml: &Nvml, raw: &RawNvml, topology: &Topology, device: &Device, dl: &[&str; 2]) {
+    match device.cpu_affinity(AFFINITY_WORDS) {
+        Ok(mask) => {
+            set_gv!(metrics.gv_cpu_affinity, &[dl[0], dl[1], range_list(&mask).as_str()], 1);
+        }
+        Err(e) => trace!("failed to collect CPU affinity: {:?}", e),
+    }
+    match raw.memory_affinity(device, AFFINITY_WORDS) {
+        Ok(mask) => {
+            if let Some(node) = set_bits(&mask).next() {
+                set_gv!(metrics.gv_numa_node, dl, node);
+            }
+        }
+        Err(e) => trace!("failed to collect NUMA affinity: {:?}", e),
+    }
+
+    let links = match topology.0.get() {
+        Some(links) => links,
+        None => match discover(nvml, raw) {
+            Ok(links) => topology.0.get_or_init(|| links),
+            Err(e) => {
+                warn!("error discovering device topology: {:?}", e);
+                return;
+            }
+        },
+    };
+    for link in links.get(dl[1]).into_iter().flatten() {
+        if let Some(level) = link.level {
+            set_gv!(metrics.gv_topology_link, &[dl[0], dl[1], link.peer.as_str(), level], 1);
+        }
+        for (capability, status) in &link.p2p {
+            set_gv!(metrics.gv_p2p_status, &[dl[0], dl[1], link.peer.as_str(), capability, p2p_status_str(status)], 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BITS: usize = c_ulong::BITS as usize;
+
+    #[test]
+    fn empty_mask_has_no_ranges() {
+        assert_eq!(set_bits(&[]).count(), 0);
+        assert_eq!(set_bits(&[0, 0]).count(), 0);
+        assert_eq!(range_list(&[0, 0]), "");
+    }
+
+    #[test]
+    fn formats_ranges_and_gaps() {
+        assert_eq!(set_bits(&[0b1011]).collect::<Vec<_>>(), [0, 1, 3]);
+        assert_eq!(range_list(&[0xffff]), "0-15");
+        assert_eq!(range_list(&[0b1011]), "0-1,3");
+        assert_eq!(range_list(&[0b1010_1111_0000]), "4-7,9,11");
+    }
+
+    #[test]
+    fn ranges_span_words() {
+        let last_bit: c_ulong = 1 << (BITS - 1);
+        assert_eq!(set_bits(&[last_bit, 1]).collect::<Vec<_>>(), [BITS - 1, BITS]);
+        assert_eq!(range_list(&[last_bit, 1]), format!("{}-{}", BITS - 1, BITS));
+        assert_eq!(range_list(&[0, 0b11]), format!("{}-{}", BITS, BITS + 1));
+        assert_eq!(range_list(&[c_ulong::MAX, c_ulong::MAX]), format!("0-{}", 2 * BITS - 1));
+    }
+}
@@ -0,0 +1,80 @@
+use log::trace;
+use log::warn;
+use nvml::enum_wrappers::unit::FanState;
+use nvml::enums::unit::LedState;
+use nvml::enums::unit::TemperatureReading;
+use nvml::error::NvmlError;
+use nvml::Nvml;
+use nvml::Unit;
+
+use crate::Metrics;
+
+/// Export the state of every S-class unit.
+pub fn collect(metrics: &Metrics, nvml: &Nvml) {
+    let count = match nvml.unit_count() {
+        Ok(count) => count,
+        Err(e) => {
+            trace!("failed to count units: {:?}", e);
+            return;
+        }
+    };
+    for unit_index in 0..count {
+        let unit = match nvml.unit_by_index(unit_index) {
+            Ok(unit) => unit,
+            Err(e) => {
+                warn!("error fetching unit {}: {:?}", unit_index, e);
+                continue;
+            }
+        };
+        if let Err(e) = collect_unit(metrics, &unit, unit_index.to_string().as_str()) {
+            warn!("error collecting unit {}: {:?}", unit_index, e);
+        }
+    }
+}
+
+fn collect_unit(metrics: &Metrics, unit: &Unit, ul: &str) -> Result<(), NvmlError> {
+    let info = unit.info()?;
+    set_gv!(metrics.gv_unit_info, &[ul, info.id.as_str(), info.name.as_str(), info.serial.as_str(), info.firmware_version.as_str()], 1);
+
+    for (sensor, reading) in [("intake", TemperatureReading::Intake), ("exhaust", TemperatureReading::Exhaust), ("board", TemperatureReading::Board)] {
+        match unit.temperature(reading) {
+            Ok(temperature) => {
+                set_gv!(metrics.gv_unit_temperature, &[ul, sensor], temperature);
+            }
+            Err(e) => trace!("failed to collect {} temperature of unit {}: {:?}", sensor, ul, e),
+        }
+    }
+
+    match unit.psu_info() {
+        Ok(psu) => {
+            set_gv!(metrics.gv_unit_psu_voltage, &[ul], psu.voltage);
+            set_gv!(metrics.gv_unit_psu_current, &[ul], psu.current);
+            set_gv!(metrics.gv_unit_psu_power_draw, &[ul], psu.power_draw);
+            set_gv!(metrics.gv_unit_psu_state, &[ul, psu.state.as_str()], 1);
+        }
+        Err(e) => warn!("error fetching PSU info of unit {}: {:?}", ul, e),
+    }
+
+    match unit.fan_info() {
+        Ok(fans) => {
+            for (fan_index, fan) in fans.fans.iter().enumerate() {
+                let fan_string = fan_index.to_string();
+                set_gv!(metrics.gv_unit_fan_speed, &[ul, fan_string.as_str()], fan.speed);
+                set_gv!(metrics.gv_unit_fan_failed, &[ul, fan_string.as_str()], if fan.state == FanState::Failed { 1 } else { 0 });
+            }
+        }
+        Err(e) => warn!("error fetching fan info of unit {}: {:?}", ul, e),
+    }
+
+    match unit.led_state() {
+        Ok(LedState::Green) => {
+            set_gv!(metrics.gv_unit_led_state, &[ul, "green", ""], 1);
+        }
+        Ok(LedState::Amber(cause)) => {
+            set_gv!(metrics.gv_unit_led_state, &[ul, "amber", cause.as_str()], 1);
+        }
+        Err(e) => warn!("error fetching LED state of unit {}: {:?}", ul, e),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use log::trace;
+use log::warn;
+use nvml::error::NvmlError;
+use nvml::Device;
+
+use crate::ffi::RawNvml;
+use crate::Metrics;
+
+/// Export vGPU type counts of a vGPU host device and the state of each of its active vGPU instances.
+pub fn collect(metrics: &Metrics, raw: &RawNvml, device: &Device, dl: &[&str; 2]) {
+    match raw.is_vgpu_host(device) {
+        Ok(true) => (),
+        Ok(false) => {
+            trace!("device {} is not a vGPU host", dl[0]);
+            return;
+        }
+        Err(NvmlError::NotSupported) | Err(NvmlError::FailedToLoadSymbol(_)) => {
+            trace!("vGPU is not supported by the driver of device {}", dl[0]);
+            return;
+        }
+        Err(e) => {
+            warn!("could not check virtualization mode, skipping vGPU metrics: {:?}", e);
+            return;
+        }
+    }
+
+    match raw.supported_vgpus(device) {
+        Ok(types) => {
+            set_gv!(metrics.gv_vgpu_supported_types, dl, types.len());
+        }
+        Err(e) => warn!("error fetching supported vGPU types: {:?}", e),
+    }
+    match raw.creatable_vgpus(device) {
+        Ok(types) => {
+            set_gv!(metrics.gv_vgpu_creatable_types, dl, types.len());
+        }
+        Err(e) => warn!("error fetching creatable vGPU types: {:?}", e),
+    }
+
+    let instances = match raw.active_vgpus(device) {
+        Ok(instances) => instances,
+        Err(e) => {
+            warn!("error fetching active vGPUs: {:?}", e);
+            return;
+        }
+    };
+    set_gv!(metrics.gv_vgpu_active_count, dl, instances.len());
+    for instance in instances {
+        if let Err(e) = collect_instance(metrics, raw, instance, dl) {
+            warn!("error collecting vGPU instance {} of device {}: {:?}", instance, dl[0], e);
+        }
+    }
+}
+
+fn collect_instance(metrics: &Metrics, raw: &RawNvml, instance: u32, dl: &[&str; 2]) -> Result<(), NvmlError> {
+    let vgpu_uuid = raw.vgpu_instance_uuid(instance)?;
+    let type_name = raw.vgpu_type_name(raw.vgpu_instance_type(instance)?)?;
+    let vm_id = raw.vgpu_instance_vm_id(instance)?;
+    let vl = &[dl[0], dl[1], vgpu_uuid.as_str()];
+
+    set_gv!(metrics.gv_vgpu_info, &[dl[0], dl[1], vgpu_uuid.as_str(), type_name.as_str(), vm_id.as_str()], 1);
+
+    match raw.vgpu_instance_fb_usage(instance) {
+        Ok(usage) => {
+            set_gv!(metrics.gv_vgpu_fb_usage, vl, usage);
+        }
+        Err(e) => warn!("error fetching vGPU framebuffer usage: {:?}", e),
+    }
+
+    match raw.vgpu_instance_encoder_stats(instance) {
+        Ok(stats) => {
+            set_gv!(metrics.gv_vgpu_encoder_stats_sessions_count, vl, stats.session_count);
+            set_gv!(metrics.gv_vgpu_encoder_stats_average_fps, vl, stats.average_fps);
+            set_gv!(metrics.gv_vgpu_encoder_stats_average_latency, vl, stats.average_latency);
+        }
+        Err(e) => warn!("error fetching vGPU encoder stats: {:?}", e),
+    }
+
+    match raw.vgpu_instance_fbc_stats(instance) {
+        Ok(stats) => {
+            set_gv!(metrics.gv_vgpu_fbc_stats_sessions_count, vl, stats.sessions_count);
+            set_gv!(metrics.gv_vgpu_fbc_stats_average_fps, vl, stats.average_fps);
+            set_gv!(metrics.gv_vgpu_fbc_stats_average_latency, vl, stats.average_latency);
+        }
+        Err(e) => warn!("error fetching vGPU frame buffer capture stats: {:?}", e),
+    }
+
+    match raw.vgpu_instance_is_licensed(instance) {
+        Ok(licensed) => {
+            set_gv!(metrics.gv_vgpu_licensed, vl, if licensed { 1 } else { 0 });
+        }
+        Err(e) => warn!("error fetching vGPU license status: {:?}", e),
+    }
+
+    Ok(())
+}
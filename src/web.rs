@@ -0,0 +1,595 @@
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::future::Future;
+use std::io;
+#[cfg(target_os = "linux")]
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::future::BoxFuture;
+use futures::Stream;
+use hyper::server::accept;
+use hyper::service::make_service_fn;
+use hyper::service::service_fn;
+use hyper::service::Service;
+use hyper::Body;
+use hyper::Request;
+use hyper::Server;
+use log::debug;
+use log::error;
+use log::warn;
+use nvml::error::NvmlError;
+use prometheus::default_registry;
+use prometheus::proto::MetricFamily;
+use serde::Serialize;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use warp::http::header;
+use warp::http::HeaderMap;
+use warp::http::Method;
+use warp::http::StatusCode;
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::Filter;
+use warp::Reply;
+
+use crate::compression::Compression;
+use crate::exposition::Created;
+use crate::exposition::Format;
+use crate::gather;
+use crate::listen::Listener;
+use crate::snapshot::ErrorResponse;
+use crate::snapshot::SingleSnapshot;
+use crate::snapshot::Snapshot;
+use crate::web_config::WebConfigFile;
+use crate::Context;
+
+const HEALTHZ_PATH: &str = "/healthz";
+const READYZ_PATH: &str = "/readyz";
+/// JSON snapshot of every device, and of a single device below it by UUID.
+const DEVICES_PATH: &str = "/api/v1/devices";
+
+/// How recently a collection must have succeeded for `/readyz` to answer without collecting.
+const READY_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// Connections accepted but not yet picked up by the server.
+const ACCEPT_BACKLOG: usize = 64;
+/// Pause after failing to accept a connection, e.g. when running out of file descriptors.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A connection, plain or TLS.
+pub trait Io: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Io for T {}
+
+/// Request handler, type erased so that any warp filter can be served.
+pub type Handler = Arc<dyn Fn(Request<Body>) -> BoxFuture<'static, Response> + Send + Sync>;
+
+/// Wrap a service, e.g. from `warp::service()`, into a [`Handler`].
+pub fn handler<S>(service: S) -> Handler
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible> + Clone + Send + Sync + 'static,
+    S::Future: Send + 'static,
+{
+    Arc::new(move |request| {
+        let mut service = service.clone();
+        Box::pin(async move {
+            match service.call(request).await {
+                Ok(response) => response,
+                Err(never) => match never {},
+            }
+        })
+    })
+}
+
+/// Serve `handler` on `listener` until `shutdown` completes, with TLS and basic auth if configured in `web_config`.
+pub async fn serve(listener: Listener, handler: Handler, web_config: Option<Arc<WebConfigFile>>, shutdown: impl Future<Output = ()>) -> hyper::Result<()> {
+    let tls = web_config.clone().filter(|web_config| web_config.tls().is_some());
+    let make_service = make_service_fn(move |_| {
+        let handler = handler.clone();
+        let web_config = web_config.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let handler = handler.clone();
+                let web_config = web_config.clone();
+                async move {
+                    let web_config = match web_config {
+                        Some(web_config) => web_config,
+                        None => return Ok::<_, Infallible>(handler(request).await),
+                    };
+                    let mut response = if web_config.authorized(request.headers()).await { handler(request).await } else { unauthorized() };
+                    for (name, value) in web_config.headers() {
+                        response.headers_mut().insert(name, value);
+                    }
+                    Ok(response)
+                }
+            }))
+        }
+    });
+    let (incoming, accepting) = incoming(listener, tls);
+    let result = Server::builder(accept::from_stream(incoming)).serve(make_service).with_graceful_shutdown(shutdown).await;
+    // wait for the listener to be closed, e.g. so that Unix sockets are removed once this returns
+    accepting.await.ok();
+    result
+}
+
+/// Accept connections in the background, completing TLS handshakes concurrently so that slow
+/// clients do not hold up others. Stops accepting and closes the listener once the returned stream
+/// is dropped, completing the returned task.
+fn incoming(listener: Listener, tls: Option<Arc<WebConfigFile>>) -> (impl Stream<Item = io::Result<Box<dyn Io>>>, JoinHandle<()>) {
+    let (sender, mut receiver) = mpsc::channel::<io::Result<Box<dyn Io>>>(ACCEPT_BACKLOG);
+    let accepting = tokio::spawn(async move {
+        loop {
+            let stream = tokio::select! {
+                _ = sender.closed() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("error accepting connection: {}", e);
+                        tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                        continue;
+                    }
+                },
+            };
+            let server_config = match tls.as_ref().and_then(|tls| tls.tls()) {
+                Some(server_config) => server_config,
+                None => {
+                    if sender.send(Ok(Box::new(stream))).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, TlsAcceptor::from(server_config).accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = sender.send(Ok(Box::new(stream))).await;
+                    }
+                    Ok(Err(e)) => debug!("TLS handshake failed: {}", e),
+                    Err(_) => debug!("TLS handshake timed out"),
+                }
+            });
+        }
+    });
+    (futures::stream::poll_fn(move |cx| receiver.poll_recv(cx)), accepting)
+}
+
+fn unauthorized() -> Response {
+    let mut response = text(StatusCode::UNAUTHORIZED, "unauthorized");
+    response.headers_mut().insert(header::WWW_AUTHENTICATE, header::HeaderValue::from_static("Basic"));
+    response
+}
+
+/// Route requests by path, so that only the telemetry path triggers a collection.
+pub fn routes(ctx: Arc<Context>) -> impl Filter<Extract = (Response,), Error = Infallible> + Clone {
+    warp::method().and(warp::path::full()).and(warp::header::headers_cloned()).and_then(move |method: Method, path: FullPath, headers: HeaderMap| {
+        let ctx = ctx.clone();
+        async move {
+            // collections and compression block, keep them off the async workers
+            let response = tokio::task::spawn_blocking(move || handle(&ctx, &method, path.as_str(), &headers)).await;
+            Ok::<_, Infallible>(response.unwrap_or_else(|e| {
+                error!("error handling request: {}", e);
+                text(StatusCode::INTERNAL_SERVER_ERROR, "internal error")
+            }))
+        }
+    })
+}
+
+/// Run a collection, recording when it succeeded.
+pub fn collect(ctx: &Arc<Context>) {
+    let _collecting = ctx.collecting.lock().unwrap();
+    collect_locked(ctx);
+}
+
+/// [`collect`] with `ctx.collecting` already held.
+fn collect_locked(ctx: &Arc<Context>) {
+    match gather(ctx.clone()) {
+        Ok(_) => {
+            *ctx.last_collection.lock().unwrap() = Some(Instant::now());
+            #[cfg(target_os = "linux")]
+            if !ctx.ready.swap(true, Ordering::Relaxed) {
+                crate::systemd::notify("READY=1");
+            }
+        }
+        Err(e) => error!("error gathering metrics: {:?}", e),
+    }
+}
+
+/// Whether a collection succeeded within `period`.
+pub fn collected_within(ctx: &Context, period: Duration) -> bool {
+    succeeded_within(*ctx.last_collection.lock().unwrap(), period)
+}
+
+fn succeeded_within(last_collection: Option<Instant>, period: Duration) -> bool {
+    last_collection.is_some_and(|at| at.elapsed() < period)
+}
+
+/// Endpoint of a request.
+#[derive(Debug, PartialEq)]
+enum Route<'a> {
+    Metrics,
+    Devices,
+    Device(&'a str),
+    Healthz,
+    Readyz,
+    LandingPage,
+    NotFound,
+    MethodNotAllowed,
+}
+
+fn route<'a>(telemetry_path: &str, method: &Method, path: &'a str) -> Route<'a> {
+    let device_uuid = path.strip_prefix(DEVICES_PATH).and_then(|rest| rest.strip_prefix('/')).filter(|uuid| !uuid.is_empty() && !uuid.contains('/'));
+    let route = match (path, device_uuid) {
+        (_, Some(uuid)) => Route::Device(uuid),
+        (p, _) if p == telemetry_path => Route::Metrics,
+        (DEVICES_PATH, _) => Route::Devices,
+        (HEALTHZ_PATH, _) => Route::Healthz,
+        (READYZ_PATH, _) => Route::Readyz,
+        ("/", _) => Route::LandingPage,
+        _ => return Route::NotFound,
+    };
+    if method != Method::GET && method != Method::HEAD {
+        return Route::MethodNotAllowed;
+    }
+    route
+}
+
+fn handle(ctx: &Arc<Context>, method: &Method, path: &str, headers: &HeaderMap) -> Response {
+    match route(&ctx.opts.telemetry_path, method, path) {
+        Route::Metrics => metrics(ctx, headers),
+        Route::Devices => api_devices(ctx),
+        Route::Device(uuid) => api_device(ctx, uuid),
+        Route::Healthz => text(StatusCode::OK, "ok"),
+        Route::Readyz => readiness(ctx),
+        Route::LandingPage => landing_page(ctx),
+        Route::NotFound => text(StatusCode::NOT_FOUND, "not found"),
+        Route::MethodNotAllowed => {
+            let mut response = text(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+            response.headers_mut().insert(header::ALLOW, header::HeaderValue::from_static("GET, HEAD"));
+            response
+        }
+    }
+}
+
+/// Ready while collections succeed. Collects when none did recently, e.g. because nothing scraped,
+/// so that readiness does not depend on being scraped.
+fn readiness(ctx: &Arc<Context>) -> Response {
+    if !collected_within(ctx, READY_MAX_AGE) {
+        collect(ctx);
+    }
+    if collected_within(ctx, READY_MAX_AGE) {
+        text(StatusCode::OK, "ready")
+    } else {
+        text(StatusCode::SERVICE_UNAVAILABLE, "not ready")
+    }
+}
+
+fn text(status: StatusCode, body: &'static str) -> Response {
+    warp::reply::with_status(body, status).into_response()
+}
+
+fn json(status: StatusCode, body: &impl Serialize) -> Response {
+    warp::reply::with_status(warp::reply::json(body), status).into_response()
+}
+
+fn api_devices(ctx: &Context) -> Response {
+    match Snapshot::collect(ctx) {
+        Ok(snapshot) => json(StatusCode::OK, &snapshot),
+        Err(e) => {
+            error!("error collecting device snapshot: {}", e);
+            json(StatusCode::INTERNAL_SERVER_ERROR, &ErrorResponse::new(e))
+        }
+    }
+}
+
+fn api_device(ctx: &Context, uuid: &str) -> Response {
+    match SingleSnapshot::collect(ctx, uuid) {
+        Ok(Some(snapshot)) => json(StatusCode::OK, &snapshot),
+        Ok(None) => json(StatusCode::NOT_FOUND, &ErrorResponse::new(format!("no device with UUID {}", uuid))),
+        Err(e) => {
+            error!("error collecting snapshot of device {}: {}", uuid, e);
+            json(StatusCode::INTERNAL_SERVER_ERROR, &ErrorResponse::new(e))
+        }
+    }
+}
+
+fn metrics(ctx: &Arc<Context>, headers: &HeaderMap) -> Response {
+    let families = {
+        let _collecting = ctx.collecting.lock().unwrap();
+        collect_locked(ctx);
+        default_registry().gather()
+    };
+    exposition(headers, &families, &ctx.created, &ctx.cumulative.counters(), &ctx.compression)
+}
+
+/// Encode `families` in the format and with the compression negotiated from the request `headers`.
+fn exposition(headers: &HeaderMap, families: &[MetricFamily], created: &Created, seeded: &HashSet<String>, compression: &Compression) -> Response {
+    let header = |name| headers.get(name).and_then(|value: &header::HeaderValue| value.to_str().ok());
+    let format = Format::negotiate(header(header::ACCEPT));
+    let body = match format.encode(families, created, seeded) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("error encoding metrics: {}", e);
+            return text(StatusCode::INTERNAL_SERVER_ERROR, "error encoding metrics");
+        }
+    };
+    let (encoding, body) = match compression.compress(header(header::ACCEPT_ENCODING), body.into()) {
+        Ok(compressed) => compressed,
+        Err(e) => {
+            error!("error compressing metrics: {}", e);
+            return text(StatusCode::INTERNAL_SERVER_ERROR, "error compressing metrics");
+        }
+    };
+
+    let mut response = Response::new(body.into());
+    response.headers_mut().insert(header::CONTENT_TYPE, header::HeaderValue::from_static(format.content_type()));
+    response.headers_mut().insert(header::VARY, header::HeaderValue::from_static("Accept, Accept-Encoding"));
+    if let Some(encoding) = encoding {
+        response.headers_mut().insert(header::CONTENT_ENCODING, header::HeaderValue::from_static(encoding.as_str()));
+    }
+    response
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Index, name and UUID of every device.
+fn devices(ctx: &Context) -> Result<Vec<(u32, String, String)>, NvmlError> {
+    (0..ctx.nvml.device_count()?)
+        .map(|index| {
+            let device = ctx.nvml.device_by_index(index)?;
+            Ok((index, device.name()?, device.uuid()?))
+        })
+        .collect()
+}
+
+fn landing_page(ctx: &Context) -> Response {
+    // links are relative, so that they keep working behind a reverse proxy serving the exporter under a prefix
+    let telemetry_link = escape(ctx.opts.telemetry_path.trim_start_matches('/'));
+    let mut html = String::new();
+    let _ = writeln!(html, "<!DOCTYPE html>\n<html>\n<head><title>NVML Exporter</title></head>\n<body>");
+    let _ = writeln!(html, "<h1>NVML Exporter</h1>\n<p>Version {}</p>", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(
+        html,
+        "<ul>\n<li><a href=\"{telemetry_link}\">Metrics</a></li>\n<li><a href=\"healthz\">Health</a></li>\n<li><a href=\"readyz\">Readiness</a></li>\n<li><a href=\"api/v1/devices\">Devices (JSON)</a></li>\n</ul>"
+    );
+    let _ = writeln!(html, "<h2>Devices</h2>");
+    match devices(ctx) {
+        Ok(devices) => {
+            let _ = writeln!(html, "<table>\n<tr><th>Index</th><th>Name</th><th>UUID</th></tr>");
+            for (index, name, uuid) in devices {
+                let _ = writeln!(html, "<tr><td>{}</td><td>{}</td><td>{}</td></tr>", index, escape(&name), escape(&uuid));
+            }
+            let _ = writeln!(html, "</table>");
+        }
+        Err(e) => {
+            let _ = writeln!(html, "<p>Error listing devices: {}</p>", escape(&e.to_string()));
+        }
+    }
+    let _ = writeln!(html, "</body>\n</html>");
+    warp::reply::html(html).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::path::Path;
+    use std::path::PathBuf;
+    use std::time::SystemTime;
+
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use base64::Engine as _;
+    use rustls::Certificate;
+    use rustls::ClientConfig;
+    use rustls::RootCertStore;
+    use rustls::ServerName;
+    use tokio::net::TcpListener;
+    use tokio::net::TcpStream;
+    use tokio::sync::oneshot;
+    use tokio_rustls::TlsConnector;
+
+    use super::*;
+
+    /// Self-signed certificate for localhost, as PEM encoded certificate and key.
+    fn self_signed() -> (String, String) {
+        let certificate = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        (certificate.serialize_pem().unwrap(), certificate.serialize_private_key_pem())
+    }
+
+    fn der(pem: &str) -> Vec<u8> {
+        rustls_pemfile::certs(&mut pem.as_bytes()).unwrap().remove(0)
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nvml-exporter-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write a certificate and key, making sure their modification time changes even on coarse grained filesystems.
+    fn write_certificate(dir: &Path, (certificate, key): &(String, String), modified: SystemTime) {
+        for (name, content) in [("cert.pem", certificate), ("key.pem", key)] {
+            std::fs::write(dir.join(name), content).unwrap();
+            std::fs::File::options().write(true).open(dir.join(name)).unwrap().set_modified(modified).unwrap();
+        }
+    }
+
+    /// Serve "ok" on localhost with the web config in `dir`.
+    async fn start(dir: &Path) -> (SocketAddr, oneshot::Sender<()>) {
+        let web_config = Arc::new(WebConfigFile::load(&dir.join("web-config.yml")).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = Listener::Tcp(listener);
+        let (stop, stopped) = oneshot::channel::<()>();
+        let handler = handler(warp::service(warp::any().map(|| "ok")));
+        tokio::spawn(serve(listener, handler, Some(web_config), async {
+            stopped.await.ok();
+        }));
+        (addr, stop)
+    }
+
+    /// Request the server over a new TLS connection, returning the response status and the server certificate.
+    async fn get(addr: SocketAddr, trusted: &[&str], authorization: Option<String>) -> (StatusCode, Certificate) {
+        let mut roots = RootCertStore::empty();
+        for pem in trusted {
+            roots.add(&Certificate(der(pem))).unwrap();
+        }
+        let config = ClientConfig::builder().with_safe_defaults().with_root_certificates(roots).with_no_client_auth();
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let stream = TlsConnector::from(Arc::new(config)).connect(ServerName::try_from("localhost").unwrap(), stream).await.unwrap();
+        let certificate = stream.get_ref().1.peer_certificates().unwrap()[0].clone();
+
+        let (mut sender, connection) = hyper::client::conn::handshake(stream).await.unwrap();
+        tokio::spawn(connection);
+        let mut request = Request::get("/metrics").header(header::HOST, "localhost");
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        let response = sender.send_request(request.body(Body::empty()).unwrap()).await.unwrap();
+        (response.status(), certificate)
+    }
+
+    #[tokio::test]
+    async fn tls_with_basic_auth() {
+        let dir = temp_dir("tls-with-basic-auth");
+        let certificate = self_signed();
+        write_certificate(&dir, &certificate, SystemTime::now());
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        std::fs::write(dir.join("web-config.yml"), format!("tls_server_config:\n  cert_file: cert.pem\n  key_file: key.pem\nbasic_auth_users:\n  prometheus: {hash}\n")).unwrap();
+
+        let (addr, _stop) = start(&dir).await;
+        let basic = |credentials: &str| Some(format!("Basic {}", BASE64.encode(credentials)));
+        assert_eq!(get(addr, &[&certificate.0], None).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(get(addr, &[&certificate.0], basic("prometheus:wrong")).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(get(addr, &[&certificate.0], basic("other:secret")).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(get(addr, &[&certificate.0], basic("prometheus:secret")).await.0, StatusCode::OK);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reloads_changed_certificate() {
+        let dir = temp_dir("reloads-changed-certificate");
+        let (first, second) = (self_signed(), self_signed());
+        let now = SystemTime::now();
+        write_certificate(&dir, &first, now);
+        std::fs::write(dir.join("web-config.yml"), "tls_server_config:\n  cert_file: cert.pem\n  key_file: key.pem\n").unwrap();
+
+        let (addr, _stop) = start(&dir).await;
+        let trusted = [first.0.as_str(), second.0.as_str()];
+        assert_eq!(get(addr, &trusted, None).await, (StatusCode::OK, Certificate(der(&first.0))));
+
+        write_certificate(&dir, &second, now + Duration::from_secs(1));
+        assert_eq!(get(addr, &trusted, None).await, (StatusCode::OK, Certificate(der(&second.0))));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_unix_socket_until_shutdown() {
+        let dir = temp_dir("serves-unix-socket");
+        let path = dir.join("exporter.sock");
+        let listener = crate::listen::ListenAddr::Unix(path.clone()).bind(false, &Default::default()).unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve(listener, handler(warp::service(warp::any().map(|| "ok"))), None, async {
+            stopped.await.ok();
+        }));
+
+        let stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        let (mut sender, connection) = hyper::client::conn::handshake(stream).await.unwrap();
+        tokio::spawn(connection);
+        let response = sender.send_request(Request::get("/metrics").header(header::HOST, "localhost").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        drop(sender);
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(!path.exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn negotiates_content_type() {
+        let families = crate::exposition::tests::families();
+        let created = Created::default();
+        let compression = Compression::new(0);
+        for (accept, content_type) in [
+            (None, "text/plain; version=0.0.4"),
+            (Some("text/plain;version=0.0.4;q=0.5,*/*;q=0.1"), "text/plain; version=0.0.4"),
+            (Some("application/openmetrics-text;version=1.0.0,text/plain;version=0.0.4;q=0.5"), "application/openmetrics-text; version=1.0.0; charset=utf-8"),
+            (
+                Some("application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;encoding=delimited;q=0.7,text/plain;version=0.0.4;q=0.3"),
+                "application/vnd.google.protobuf; proto=io.prometheus.client.MetricFamily; encoding=delimited",
+            ),
+        ] {
+            let mut headers = HeaderMap::new();
+            if let Some(accept) = accept {
+                headers.insert(header::ACCEPT, header::HeaderValue::from_static(accept));
+            }
+            let response = exposition(&headers, &families, &created, &Default::default(), &compression);
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::CONTENT_TYPE], content_type, "{:?}", accept);
+            assert_eq!(response.headers()[header::VARY], "Accept, Accept-Encoding");
+            assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+        }
+    }
+
+    #[test]
+    fn compresses_when_accepted() {
+        let families = crate::exposition::tests::families();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_ENCODING, header::HeaderValue::from_static("gzip, deflate"));
+
+        let response = exposition(&headers, &families, &Created::default(), &Default::default(), &Compression::new(0));
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/plain; version=0.0.4");
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+
+        // below the threshold
+        let response = exposition(&headers, &families, &Created::default(), &Default::default(), &Compression::new(usize::MAX));
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+    }
+
+    #[test]
+    fn routes_by_path_and_method() {
+        let get = |path| route("/metrics", &Method::GET, path);
+        assert_eq!(get("/metrics"), Route::Metrics);
+        assert_eq!(route("/metrics", &Method::HEAD, "/metrics"), Route::Metrics);
+        assert_eq!(get("/"), Route::LandingPage);
+        assert_eq!(get("/healthz"), Route::Healthz);
+        assert_eq!(get("/readyz"), Route::Readyz);
+        assert_eq!(get("/api/v1/devices"), Route::Devices);
+        assert_eq!(get("/api/v1/devices/GPU-0"), Route::Device("GPU-0"));
+
+        for path in ["/metrics/", "/metricsz", "/favicon.ico", "/api/v1/devices/", "/api/v1/devices/GPU-0/x", "/api/v1/devicesGPU-0"] {
+            assert_eq!(get(path), Route::NotFound, "{}", path);
+        }
+        assert_eq!(route("/custom", &Method::GET, "/metrics"), Route::NotFound);
+        assert_eq!(route("/custom", &Method::GET, "/custom"), Route::Metrics);
+
+        for method in [Method::POST, Method::PUT, Method::DELETE, Method::OPTIONS] {
+            assert_eq!(route("/metrics", &method, "/metrics"), Route::MethodNotAllowed);
+            assert_eq!(route("/metrics", &method, "/readyz"), Route::MethodNotAllowed);
+            // unknown paths are not found whatever the method
+            assert_eq!(route("/metrics", &method, "/unknown"), Route::NotFound);
+        }
+    }
+
+    #[test]
+    fn ready_after_recent_success() {
+        assert!(!succeeded_within(None, READY_MAX_AGE));
+        assert!(succeeded_within(Some(Instant::now()), READY_MAX_AGE));
+        let stale = Instant::now().checked_sub(READY_MAX_AGE * 2).unwrap();
+        assert!(!succeeded_within(Some(stale), READY_MAX_AGE));
+    }
+}
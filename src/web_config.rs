@@ -0,0 +1,607 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::IpAddr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use anyhow::bail;
+use anyhow::Context as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use log::info;
+use log::warn;
+use rustls::kx_group;
+use rustls::server::AllowAnyAnonymousOrAuthenticatedClient;
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::server::ClientCertVerified;
+use rustls::server::ClientCertVerifier;
+use rustls::server::NoClientAuth;
+use rustls::Certificate;
+use rustls::DistinguishedName;
+use rustls::PrivateKey;
+use rustls::RootCertStore;
+use rustls::ServerConfig;
+use rustls::SupportedCipherSuite;
+use rustls::SupportedKxGroup;
+use rustls::SupportedProtocolVersion;
+use serde::Deserialize;
+use warp::http::header;
+use warp::http::HeaderMap;
+use warp::http::HeaderName;
+use warp::http::HeaderValue;
+
+/// Web server configuration, using the schema of the Prometheus exporter-toolkit web config file.
+#[derive(Default, Deserialize)]
+struct WebConfig {
+    tls_server_config: Option<TlsServerConfig>,
+    #[serde(default)]
+    http_server_config: HttpServerConfig,
+    /// bcrypt hashed password per user.
+    #[serde(default)]
+    basic_auth_users: HashMap<String, String>,
+    /// Keys not supported by this exporter, ignored with a warning so that toolkit configs still load.
+    #[serde(flatten)]
+    unknown: BTreeMap<String, serde_yaml::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct HttpServerConfig {
+    /// Offer HTTP/2 to TLS clients.
+    http2: bool,
+    /// Headers added to every response.
+    headers: BTreeMap<String, String>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, serde_yaml::Value>,
+}
+
+impl Default for HttpServerConfig {
+    fn default() -> Self {
+        HttpServerConfig {
+            http2: true,
+            headers: Default::default(),
+            unknown: Default::default(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TlsServerConfig {
+    cert_file: PathBuf,
+    key_file: PathBuf,
+    #[serde(default)]
+    client_auth_type: ClientAuthType,
+    client_ca_file: Option<PathBuf>,
+    /// DNS names and IP addresses of which a verified client certificate must have at least one.
+    #[serde(default)]
+    client_allowed_sans: Vec<String>,
+    #[serde(default = "default_min_version")]
+    min_version: TlsVersion,
+    #[serde(default = "default_max_version")]
+    max_version: TlsVersion,
+    /// TLS 1.2 cipher suites, TLS 1.3 cipher suites are always enabled like in Go.
+    #[serde(default)]
+    cipher_suites: Vec<String>,
+    prefer_server_cipher_suites: Option<bool>,
+    /// Key exchange groups, by their Go name.
+    #[serde(default)]
+    curve_preferences: Vec<String>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, serde_yaml::Value>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+enum ClientAuthType {
+    #[default]
+    NoClientCert,
+    RequestClientCert,
+    RequireAnyClientCert,
+    VerifyClientCertIfGiven,
+    RequireAndVerifyClientCert,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, PartialOrd)]
+enum TlsVersion {
+    TLS10,
+    TLS11,
+    TLS12,
+    TLS13,
+}
+
+fn default_min_version() -> TlsVersion {
+    TlsVersion::TLS12
+}
+
+fn default_max_version() -> TlsVersion {
+    TlsVersion::TLS13
+}
+
+const TLS_VERSIONS: [(TlsVersion, &SupportedProtocolVersion); 2] = [(TlsVersion::TLS12, &rustls::version::TLS12), (TlsVersion::TLS13, &rustls::version::TLS13)];
+
+/// Key exchange groups by their Go name, CurveP521 is not supported by rustls.
+const CURVES: [(&str, &SupportedKxGroup); 3] = [("X25519", &kx_group::X25519), ("CurveP256", &kx_group::SECP256R1), ("CurveP384", &kx_group::SECP384R1)];
+
+/// Accepts any client certificate, for the client auth types that do not verify it.
+struct AnyClientCert {
+    mandatory: bool,
+}
+
+impl ClientCertVerifier for AnyClientCert {
+    fn client_auth_mandatory(&self) -> bool {
+        self.mandatory
+    }
+
+    fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(&self, _end_entity: &Certificate, _intermediates: &[Certificate], _now: SystemTime) -> Result<ClientCertVerified, rustls::Error> {
+        Ok(ClientCertVerified::assertion())
+    }
+}
+
+/// Requires a verified client certificate to have one of the allowed subject alternative names.
+struct AllowedSans {
+    verifier: Arc<dyn ClientCertVerifier>,
+    sans: Vec<String>,
+}
+
+impl AllowedSans {
+    fn allowed(&self, end_entity: &Certificate) -> bool {
+        let certificate = match webpki::EndEntityCert::try_from(end_entity.0.as_slice()) {
+            Ok(certificate) => certificate,
+            Err(_) => return false,
+        };
+        let dns_names = certificate.dns_names().map(|names| names.map(|name| <&str>::from(name).to_string()).collect::<Vec<_>>()).unwrap_or_default();
+        self.sans.iter().any(|san| {
+            dns_names.iter().any(|name| name.eq_ignore_ascii_case(san)) || (san.parse::<IpAddr>().is_ok() && webpki::SubjectNameRef::try_from_ascii_str(san).is_ok_and(|ip| certificate.verify_is_valid_for_subject_name(ip).is_ok()))
+        })
+    }
+}
+
+impl ClientCertVerifier for AllowedSans {
+    fn offer_client_auth(&self) -> bool {
+        self.verifier.offer_client_auth()
+    }
+
+    fn client_auth_mandatory(&self) -> bool {
+        self.verifier.client_auth_mandatory()
+    }
+
+    fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+        self.verifier.client_auth_root_subjects()
+    }
+
+    fn verify_client_cert(&self, end_entity: &Certificate, intermediates: &[Certificate], now: SystemTime) -> Result<ClientCertVerified, rustls::Error> {
+        let verified = self.verifier.verify_client_cert(end_entity, intermediates, now)?;
+        if !self.allowed(end_entity) {
+            return Err(rustls::Error::General("client certificate has none of the allowed SANs".to_string()));
+        }
+        Ok(verified)
+    }
+}
+
+/// Cipher suite name as used by Go, e.g. `TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256` or `TLS_AES_128_GCM_SHA256`.
+fn cipher_suite_name(suite: &SupportedCipherSuite) -> String {
+    format!("{:?}", suite.suite()).replacen("TLS13_", "TLS_", 1)
+}
+
+fn certificates(path: &Path) -> anyhow::Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(path).with_context(|| format!("could not open {}", path.display()))?);
+    let certificates = rustls_pemfile::certs(&mut reader).with_context(|| format!("could not read certificates from {}", path.display()))?;
+    if certificates.is_empty() {
+        bail!("no certificate found in {}", path.display());
+    }
+    Ok(certificates.into_iter().map(Certificate).collect())
+}
+
+fn private_key(path: &Path) -> anyhow::Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path).with_context(|| format!("could not open {}", path.display()))?);
+    for item in rustls_pemfile::read_all(&mut reader).with_context(|| format!("could not read private key from {}", path.display()))? {
+        match item {
+            rustls_pemfile::Item::RSAKey(key) | rustls_pemfile::Item::PKCS8Key(key) | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => (),
+        }
+    }
+    bail!("no private key found in {}", path.display())
+}
+
+impl TlsServerConfig {
+    /// Files the TLS configuration is read from, relative paths being relative to the config file.
+    fn files(&self, dir: &Path) -> Vec<PathBuf> {
+        [Some(&self.cert_file), Some(&self.key_file), self.client_ca_file.as_ref()].into_iter().flatten().map(|path| dir.join(path)).collect()
+    }
+
+    fn server_config(&self, dir: &Path, http2: bool) -> anyhow::Result<ServerConfig> {
+        let versions = TLS_VERSIONS
+            .iter()
+            .filter(|(version, _)| self.min_version <= *version && *version <= self.max_version)
+            .map(|(_, version)| *version)
+            .collect::<Vec<_>>();
+        if versions.is_empty() {
+            bail!("no supported TLS version between {:?} and {:?}, only TLS12 and TLS13 are supported", self.min_version, self.max_version);
+        }
+
+        let cipher_suites = if self.cipher_suites.is_empty() {
+            rustls::DEFAULT_CIPHER_SUITES.to_vec()
+        } else {
+            for name in &self.cipher_suites {
+                if !rustls::ALL_CIPHER_SUITES.iter().any(|suite| cipher_suite_name(suite) == *name) {
+                    bail!("unsupported cipher suite {}", name);
+                }
+            }
+            rustls::ALL_CIPHER_SUITES
+                .iter()
+                .filter(|suite| suite.version() == &rustls::version::TLS13 || self.cipher_suites.contains(&cipher_suite_name(suite)))
+                .copied()
+                .collect()
+        };
+
+        let kx_groups = if self.curve_preferences.is_empty() {
+            rustls::ALL_KX_GROUPS.to_vec()
+        } else {
+            self.curve_preferences
+                .iter()
+                .map(|name| match CURVES.iter().find(|(curve, _)| curve == name) {
+                    Some((_, group)) => Ok(*group),
+                    None => bail!("unsupported curve {}", name),
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?
+        };
+
+        let roots = match &self.client_ca_file {
+            Some(path) => {
+                let mut roots = RootCertStore::empty();
+                for certificate in certificates(&dir.join(path))? {
+                    roots.add(&certificate).with_context(|| format!("invalid client CA certificate in {}", path.display()))?;
+                }
+                Some(roots)
+            }
+            None => None,
+        };
+        let verifier = match (self.client_auth_type, roots) {
+            (ClientAuthType::NoClientCert, None) => NoClientAuth::boxed(),
+            (ClientAuthType::NoClientCert, Some(_)) => bail!("client_ca_file is set but client_auth_type is NoClientCert"),
+            (ClientAuthType::RequestClientCert, _) => Arc::new(AnyClientCert { mandatory: false }),
+            (ClientAuthType::RequireAnyClientCert, _) => Arc::new(AnyClientCert { mandatory: true }),
+            (ClientAuthType::VerifyClientCertIfGiven, Some(roots)) => AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed(),
+            (ClientAuthType::RequireAndVerifyClientCert, Some(roots)) => AllowAnyAuthenticatedClient::new(roots).boxed(),
+            (client_auth_type, None) => bail!("client_ca_file is required for client_auth_type {:?}", client_auth_type),
+        };
+        let verifier = match self.client_auth_type {
+            _ if self.client_allowed_sans.is_empty() => verifier,
+            ClientAuthType::VerifyClientCertIfGiven | ClientAuthType::RequireAndVerifyClientCert => Arc::new(AllowedSans {
+                verifier,
+                sans: self.client_allowed_sans.clone(),
+            }),
+            client_auth_type => bail!("client_allowed_sans requires verifying client certificates, not client_auth_type {:?}", client_auth_type),
+        };
+
+        let mut config = ServerConfig::builder()
+            .with_cipher_suites(&cipher_suites)
+            .with_kx_groups(&kx_groups)
+            .with_protocol_versions(&versions)?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certificates(&dir.join(&self.cert_file))?, private_key(&dir.join(&self.key_file))?)?;
+        config.ignore_client_order = self.prefer_server_cipher_suites.unwrap_or(true);
+        config.alpn_protocols = if http2 { vec![b"h2".to_vec(), b"http/1.1".to_vec()] } else { vec![b"http/1.1".to_vec()] };
+        Ok(config)
+    }
+}
+
+/// Maximum number of verified `Authorization` headers remembered per config.
+const AUTHORIZED_CACHE_SIZE: usize = 256;
+
+/// SHA-256 of an `Authorization` header, so that remembered credentials are not kept in memory.
+type AuthorizationDigest = [u8; 32];
+
+fn authorization_digest(authorization: &str) -> AuthorizationDigest {
+    ring::digest::digest(&ring::digest::SHA256, authorization.as_bytes()).as_ref().try_into().unwrap()
+}
+
+/// A successfully loaded web config.
+struct Loaded {
+    tls: Option<Arc<ServerConfig>>,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    users: HashMap<String, String>,
+    /// Hash checked for unknown users, with the highest cost of the users' hashes so that unknown
+    /// users take as long to reject as known users.
+    dummy_hash: String,
+    /// `Authorization` headers already verified, since bcrypt is slow on purpose.
+    authorized: Mutex<HashSet<AuthorizationDigest>>,
+    /// The config file and the files it references.
+    files: Vec<PathBuf>,
+}
+
+impl Loaded {
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path).with_context(|| format!("could not read {}", path.display()))?;
+        let config: WebConfig = if content.trim().is_empty() {
+            Default::default()
+        } else {
+            serde_yaml::from_str(&content).with_context(|| format!("could not parse {}", path.display()))?
+        };
+        let unknown = config.unknown.keys().map(String::from);
+        let unknown = unknown.chain(config.tls_server_config.iter().flat_map(|tls| tls.unknown.keys().map(|key| format!("tls_server_config.{key}"))));
+        for key in unknown.chain(config.http_server_config.unknown.keys().map(|key| format!("http_server_config.{key}"))) {
+            warn!("ignoring unsupported key {} in {}", key, path.display());
+        }
+        let headers = config
+            .http_server_config
+            .headers
+            .iter()
+            .map(|(name, value)| Ok((HeaderName::from_str(name)?, HeaderValue::from_str(value)?)))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .context("invalid http_server_config.headers")?;
+        let mut cost = None;
+        for (user, hash) in &config.basic_auth_users {
+            let parts = bcrypt::HashParts::from_str(hash).with_context(|| format!("invalid bcrypt hash for user {user}"))?;
+            cost = cost.max(Some(parts.get_cost()));
+        }
+        let dummy_hash = match cost {
+            Some(cost) => bcrypt::hash("", cost)?,
+            None => String::new(),
+        };
+
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let mut files = vec![path.to_path_buf()];
+        let tls = match &config.tls_server_config {
+            Some(tls) => {
+                files.extend(tls.files(dir));
+                Some(Arc::new(tls.server_config(dir, config.http_server_config.http2).context("invalid tls_server_config")?))
+            }
+            None => None,
+        };
+        Ok(Loaded {
+            tls,
+            headers,
+            users: config.basic_auth_users,
+            dummy_hash,
+            authorized: Default::default(),
+            files,
+        })
+    }
+
+    /// Verify the credentials of an `Authorization` header, which takes a full bcrypt cost unless
+    /// the header was verified before.
+    fn verify(&self, authorization: &str) -> bool {
+        let digest = authorization_digest(authorization);
+        if self.authorized.lock().unwrap().contains(&digest) {
+            return true;
+        }
+
+        let credentials = match authorization.strip_prefix("Basic ").and_then(|encoded| BASE64.decode(encoded).ok()).and_then(|decoded| String::from_utf8(decoded).ok()) {
+            Some(credentials) => credentials,
+            None => return false,
+        };
+        let (user, password) = match credentials.split_once(':') {
+            Some(credentials) => credentials,
+            None => return false,
+        };
+        let valid = match self.users.get(user) {
+            Some(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            None => {
+                let _ = bcrypt::verify(password, &self.dummy_hash);
+                false
+            }
+        };
+        if valid {
+            let mut authorized = self.authorized.lock().unwrap();
+            if authorized.len() >= AUTHORIZED_CACHE_SIZE {
+                authorized.clear();
+            }
+            authorized.insert(digest);
+        }
+        valid
+    }
+}
+
+fn modification_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files.iter().map(|file| std::fs::metadata(file).and_then(|metadata| metadata.modified()).ok()).collect()
+}
+
+/// Web config file passed to `--web.config.file`, reloaded whenever it or a file it references changes.
+pub struct WebConfigFile {
+    path: PathBuf,
+    state: Mutex<(Vec<Option<SystemTime>>, Arc<Loaded>)>,
+}
+
+impl WebConfigFile {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let loaded = Loaded::load(path)?;
+        Ok(WebConfigFile {
+            path: path.to_path_buf(),
+            state: Mutex::new((modification_times(&loaded.files), Arc::new(loaded))),
+        })
+    }
+
+    /// The current config, reloaded first if any of its files changed. An invalid config is
+    /// logged and ignored until its files change again.
+    fn current(&self) -> Arc<Loaded> {
+        let mut state = self.state.lock().unwrap();
+        let times = modification_times(&state.1.files);
+        if times != state.0 {
+            match Loaded::load(&self.path) {
+                Ok(loaded) if loaded.tls.is_some() != state.1.tls.is_some() => {
+                    warn!("enabling or disabling TLS in {} requires a restart, keeping the previous web config", self.path.display());
+                    state.0 = times;
+                }
+                Ok(loaded) => {
+                    info!("reloaded web config {}", self.path.display());
+                    *state = (modification_times(&loaded.files), Arc::new(loaded));
+                }
+                Err(e) => {
+                    warn!("error reloading web config {}, keeping the previous one: {:#}", self.path.display(), e);
+                    state.0 = times;
+                }
+            }
+        }
+        state.1.clone()
+    }
+
+    /// TLS configuration for new connections, `None` when serving plain HTTP.
+    pub fn tls(&self) -> Option<Arc<ServerConfig>> {
+        self.current().tls.clone()
+    }
+
+    /// Headers added to every response.
+    pub fn headers(&self) -> Vec<(HeaderName, HeaderValue)> {
+        self.current().headers.clone()
+    }
+
+    /// Check the basic auth credentials of a request, if any users are configured.
+    pub async fn authorized(&self, headers: &HeaderMap) -> bool {
+        let loaded = self.current();
+        if loaded.users.is_empty() {
+            return true;
+        }
+        let authorization = match headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok()) {
+            Some(authorization) => authorization.to_string(),
+            None => return false,
+        };
+        // bcrypt is slow on purpose, keep it off the async workers
+        tokio::task::spawn_blocking(move || loaded.verify(&authorization)).await.unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nvml-exporter-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn ca() -> rcgen::Certificate {
+        let mut params = rcgen::CertificateParams::new(vec![]);
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        rcgen::Certificate::from_params(params).unwrap()
+    }
+
+    fn der(pem: &str) -> Certificate {
+        Certificate(rustls_pemfile::certs(&mut pem.as_bytes()).unwrap().remove(0))
+    }
+
+    #[test]
+    fn loads_exporter_toolkit_config() {
+        let dir = temp_dir("loads-exporter-toolkit-config");
+        let server = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(dir.join("server.crt"), server.serialize_pem().unwrap()).unwrap();
+        std::fs::write(dir.join("server.key"), server.serialize_private_key_pem()).unwrap();
+        std::fs::write(dir.join("ca.crt"), ca().serialize_pem().unwrap()).unwrap();
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        // every key documented for the exporter-toolkit web config, plus one it may add later
+        std::fs::write(
+            dir.join("web-config.yml"),
+            format!(
+                "tls_server_config:
+  cert_file: server.crt
+  key_file: server.key
+  client_auth_type: RequireAndVerifyClientCert
+  client_ca_file: ca.crt
+  client_allowed_sans:
+    - prometheus.example.com
+    - 10.0.0.1
+  min_version: TLS12
+  max_version: TLS13
+  cipher_suites:
+    - TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256
+    - TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384
+  prefer_server_cipher_suites: true
+  curve_preferences:
+    - X25519
+    - CurveP256
+    - CurveP384
+http_server_config:
+  http2: false
+  headers:
+    Content-Security-Policy: default-src 'self'
+    Strict-Transport-Security: max-age=31536000
+    X-Content-Type-Options: nosniff
+    X-Frame-Options: deny
+    X-XSS-Protection: 1; mode=block
+  future_option: true
+basic_auth_users:
+  prometheus: {hash}
+"
+            ),
+        )
+        .unwrap();
+
+        let loaded = Loaded::load(&dir.join("web-config.yml")).unwrap();
+        let tls = loaded.tls.unwrap();
+        assert_eq!(tls.alpn_protocols, vec![b"http/1.1".to_vec()]);
+        assert!(tls.ignore_client_order);
+        assert_eq!(loaded.headers.len(), 5);
+        assert_eq!(loaded.headers[4], (HeaderName::from_static("x-xss-protection"), HeaderValue::from_static("1; mode=block")));
+        assert_eq!(loaded.users.len(), 1);
+        assert_eq!(loaded.files.len(), 4);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn verifies_basic_auth() {
+        let dir = temp_dir("verifies-basic-auth");
+        let (fast, slow) = (bcrypt::hash("secret", 4).unwrap(), bcrypt::hash("secret", 5).unwrap());
+        std::fs::write(dir.join("web-config.yml"), format!("basic_auth_users:\n  fast: {fast}\n  slow: {slow}\n")).unwrap();
+        let loaded = Loaded::load(&dir.join("web-config.yml")).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+
+        // unknown users are checked against a hash as slow as the slowest user's
+        assert_eq!(bcrypt::HashParts::from_str(&loaded.dummy_hash).unwrap().get_cost(), 5);
+        let basic = |credentials: &str| format!("Basic {}", BASE64.encode(credentials));
+        assert!(loaded.verify(&basic("fast:secret")));
+        assert!(loaded.verify(&basic("slow:secret")));
+        assert!(!loaded.verify(&basic("fast:wrong")));
+        assert!(!loaded.verify(&basic("other:secret")));
+        assert_eq!(*loaded.authorized.lock().unwrap(), HashSet::from([authorization_digest(&basic("fast:secret")), authorization_digest(&basic("slow:secret"))]));
+
+        // the remembered headers are bounded
+        *loaded.authorized.lock().unwrap() = (0..AUTHORIZED_CACHE_SIZE).map(|i| authorization_digest(&i.to_string())).collect();
+        assert!(loaded.verify(&basic("fast:secret")));
+        assert_eq!(*loaded.authorized.lock().unwrap(), HashSet::from([authorization_digest(&basic("fast:secret"))]));
+    }
+
+    #[test]
+    fn rejects_unsupported_curves() {
+        let config: TlsServerConfig = serde_yaml::from_str("cert_file: server.crt\nkey_file: server.key\ncurve_preferences: [CurveP521]\n").unwrap();
+        let error = config.server_config(Path::new("."), true).unwrap_err();
+        assert_eq!(error.to_string(), "unsupported curve CurveP521");
+    }
+
+    #[test]
+    fn requires_allowed_sans() {
+        let ca = ca();
+        let mut roots = RootCertStore::empty();
+        roots.add(&der(&ca.serialize_pem().unwrap())).unwrap();
+        let verifier = AllowedSans {
+            verifier: AllowAnyAuthenticatedClient::new(roots).boxed(),
+            sans: vec!["prometheus.example.com".to_string(), "10.0.0.1".to_string()],
+        };
+        let client = |sans: &[&str]| {
+            let certificate = rcgen::Certificate::from_params(rcgen::CertificateParams::new(sans.iter().map(|san| san.to_string()).collect::<Vec<_>>())).unwrap();
+            der(&certificate.serialize_pem_with_signer(&ca).unwrap())
+        };
+
+        for sans in [&["prometheus.example.com"][..], &["other.example.com", "10.0.0.1"]] {
+            assert!(verifier.verify_client_cert(&client(sans), &[], SystemTime::now()).is_ok(), "{sans:?}");
+        }
+        for sans in [&["other.example.com"][..], &["10.0.0.2"], &["*.example.com"]] {
+            assert!(verifier.verify_client_cert(&client(sans), &[], SystemTime::now()).is_err(), "{sans:?}");
+        }
+        let unverified = der(&rcgen::generate_simple_self_signed(vec!["prometheus.example.com".to_string()]).unwrap().serialize_pem().unwrap());
+        assert!(verifier.verify_client_cert(&unverified, &[], SystemTime::now()).is_err());
+    }
+}